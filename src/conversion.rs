@@ -1,14 +1,108 @@
 //! This module provides [`flatten()`] and [`unflatten()`] to do the conversions
 //! between nested and flattened YAML values.
 
+use crate::path::Path;
+use crate::path::PathSegment;
 use serde_yaml_ng::Mapping;
 use serde_yaml_ng::Value;
+use std::cmp::min;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::iter::Enumerate;
 
 const DOT: &str = ".";
+const BACKSLASH: char = '\\';
+
+/// Options to customize the behavior of [`flatten_with()`].
+///
+/// The default options make [`flatten_with()`] behave exactly like [`flatten()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlattenOptions {
+    separator: String,
+    escaping: bool,
+    flatten_sequences: bool,
+    max_depth: Option<usize>,
+}
+
+impl Default for FlattenOptions {
+    fn default() -> Self {
+        Self {
+            separator: DOT.to_string(),
+            escaping: false,
+            flatten_sequences: false,
+            max_depth: None,
+        }
+    }
+}
+
+impl FlattenOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the separator used to join the tokens of a path, e.g., `/`, `__`
+    /// or `::`.
+    ///
+    /// Default to `.`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `separator` is empty.
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        assert!(!self.separator.is_empty(), "separator cannot be empty");
+        self
+    }
+
+    /// If set to `true`, separators and backslashes in mapping keys will be
+    /// escaped with a backslash, e.g., `cluster: {node.attr: x}` becomes
+    /// `cluster.node\.attr: x` rather than `cluster.node.attr: x`, so that
+    /// [`unflatten_with()`] can restore the original structure with
+    /// [`UnflattenOptions::escaping()`] enabled.
+    ///
+    /// String keys that look like booleans or numbers, e.g., `"0"` or
+    /// `"true"`, are marked with a redundant escape, e.g., `\0`, so that they
+    /// are neither treated as sequence indexes nor inferred as other types.
+    ///
+    /// The separator should not contain a backslash if this is enabled.
+    ///
+    /// Default to `false`, as the dots in partially flattened input like
+    /// `cluster.name: x` are usually meant to be split.
+    pub fn escaping(mut self, escaping: bool) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// If set to `true`, sequences will be flattened as well, using the
+    /// element indexes as path tokens, e.g., `a: [x, y]` becomes `a.0: x`
+    /// and `a.1: y`.
+    ///
+    /// Empty sequences have no element to flatten, they are kept as they are.
+    ///
+    /// Default to `false`, in which case a sequence is stored as a whole
+    /// under its parent path.
+    pub fn flatten_sequences(mut self, flatten_sequences: bool) -> Self {
+        self.flatten_sequences = flatten_sequences;
+        self
+    }
+
+    /// Sets the maximum number of tokens in a path, flattening a deeper
+    /// value leads to [`Error::DepthExceeded`], e.g., with a limit of `2`,
+    /// `a: {b: x}` can be flattened but `a: {b: {c: x}}` cannot.
+    ///
+    /// Default to no limit.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
 
 /// Flattens the `input` YAML value.
 ///
+/// This is equivalent to calling [`flatten_with()`] with the default
+/// [`FlattenOptions`].
+///
 /// # Examples
 ///
 /// ```rust
@@ -31,165 +125,1994 @@ const DOT: &str = ".";
 ///     BTreeMap::from([(String::from("a.b.c"), Value::Null)])
 /// );
 /// ```
+///
+/// # Panics
+///
+/// Panics if `input` contains a mapping key that cannot be flattened, use
+/// [`try_flatten()`] to handle that as an error.
 pub fn flatten(input: Value) -> BTreeMap<String, Value> {
-    let mut output = BTreeMap::new();
-    let mut path = Vec::new();
-    _flatten(&mut output, &mut path, input);
+    flatten_with(input, &FlattenOptions::default())
+}
+
+/// Flattens the `input` YAML value according to the given `options`.
+///
+/// Tagged values, e.g., `!secret foo`, are never flattened, they are stored
+/// as a whole under their parent path so that the tags are preserved.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::flatten_with;
+/// # use serde_yaml_nested::conversion::FlattenOptions;
+/// # use std::collections::BTreeMap;
+/// let nested: Value = from_str("a: [x, y]").unwrap();
+///
+/// let flattened = flatten_with(nested, &FlattenOptions::new().flatten_sequences(true));
+/// assert_eq!(
+///     flattened,
+///     BTreeMap::from([
+///         (String::from("a.0"), Value::String("x".into())),
+///         (String::from("a.1"), Value::String("y".into())),
+///     ])
+/// );
+/// ```
+///
+/// # Panics
+///
+/// Panics if `input` contains a mapping key that cannot be flattened, use
+/// [`try_flatten_with()`] to handle that as an error.
+pub fn flatten_with(input: Value, options: &FlattenOptions) -> BTreeMap<String, Value> {
+    try_flatten_with(input, options).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// The fallible version of [`flatten()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::try_flatten;
+/// # use serde_yaml_nested::conversion::Error;
+/// let nested: Value = from_str("a: {[b, c]: d}").unwrap();
+///
+/// let error = try_flatten(nested).unwrap_err();
+/// assert_eq!(
+///     error,
+///     Error::UnsupportedKey {
+///         path: String::from("a"),
+///         key: from_str("[b, c]").unwrap(),
+///     }
+/// );
+/// ```
+pub fn try_flatten(input: Value) -> Result<BTreeMap<String, Value>, Error> {
+    try_flatten_with(input, &FlattenOptions::default())
+}
+
+/// The fallible version of [`flatten_with()`].
+///
+/// Only string, boolean and number mapping keys can be flattened, other keys
+/// lead to [`Error::UnsupportedKey`], or [`Error::UnsupportedTag`] if they are
+/// tagged.
+pub fn try_flatten_with(
+    input: Value,
+    options: &FlattenOptions,
+) -> Result<BTreeMap<String, Value>, Error> {
+    try_flatten_ordered_with(input, options).map(|output| output.into_iter().collect())
+}
+
+/// Flattens the `input` YAML value, keeping the order of the keys in the
+/// document rather than sorting them like [`flatten()`].
+///
+/// Paths that appear more than once, e.g., with both `a.b: 1` and
+/// `a: {b: 2}`, are all kept, whereas [`flatten()`] keeps the last one.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::flatten_ordered;
+/// let nested: Value = from_str(
+///     r#"
+/// node:
+///   name: node-1
+/// cluster:
+///   name: es
+/// "#,
+/// )
+/// .unwrap();
+///
+/// let flattened = flatten_ordered(nested);
+/// assert_eq!(
+///     flattened,
+///     vec![
+///         (String::from("node.name"), Value::String("node-1".into())),
+///         (String::from("cluster.name"), Value::String("es".into())),
+///     ]
+/// );
+/// ```
+///
+/// # Panics
+///
+/// Panics if `input` contains a mapping key that cannot be flattened, use
+/// [`try_flatten_ordered_with()`] to handle that as an error.
+pub fn flatten_ordered(input: Value) -> Vec<(String, Value)> {
+    try_flatten_ordered_with(input, &FlattenOptions::default())
+        .unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// The fallible version of [`flatten_ordered()`], which accepts `options`.
+pub fn try_flatten_ordered_with(
+    input: Value,
+    options: &FlattenOptions,
+) -> Result<Vec<(String, Value)>, Error> {
+    Flatten::new(input, options).collect()
+}
+
+/// Flattens the `input` YAML value without consuming it, the leaves are
+/// borrowed from `input` rather than moved out of it.
+///
+/// This is equivalent to calling [`try_flatten_ref_with()`] with the default
+/// [`FlattenOptions`] and panicking on error.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::flatten_ref;
+/// # use std::collections::BTreeMap;
+/// let nested: Value = from_str("a: {b: 1}").unwrap();
+///
+/// let flattened = flatten_ref(&nested);
+/// assert_eq!(
+///     flattened,
+///     BTreeMap::from([(String::from("a.b"), &nested["a"]["b"])])
+/// );
+/// ```
+///
+/// # Panics
+///
+/// Panics if `input` contains a mapping key that cannot be flattened, use
+/// [`try_flatten_ref_with()`] to handle that as an error.
+pub fn flatten_ref(input: &Value) -> BTreeMap<String, &Value> {
+    try_flatten_ref_with(input, &FlattenOptions::default())
+        .unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// The borrowing version of [`try_flatten_with()`].
+pub fn try_flatten_ref_with<'a>(
+    input: &'a Value,
+    options: &FlattenOptions,
+) -> Result<BTreeMap<String, &'a Value>, Error> {
+    FlattenRef::new(input, options).collect()
+}
+
+/// Flattens the `input` YAML value into [`Path`]s rather than joined strings,
+/// keeping the order of the keys in the document.
+///
+/// As the segments are kept apart, the separator and escaping settings in
+/// `options` are not used. Boolean and number mapping keys become string
+/// segments.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::try_flatten_paths_with;
+/// # use serde_yaml_nested::conversion::FlattenOptions;
+/// # use serde_yaml_nested::path::Path;
+/// # use serde_yaml_nested::path::PathSegment;
+/// let nested: Value = from_str("a: {b.c: [x]}").unwrap();
+///
+/// let options = FlattenOptions::new().flatten_sequences(true);
+/// let flattened = try_flatten_paths_with(nested, &options).unwrap();
+/// assert_eq!(
+///     flattened,
+///     vec![(
+///         Path::from_iter([
+///             PathSegment::Key("a".into()),
+///             PathSegment::Key("b.c".into()),
+///             PathSegment::Index(0),
+///         ]),
+///         Value::String("x".into()),
+///     )]
+/// );
+/// ```
+pub fn try_flatten_paths_with(
+    input: Value,
+    options: &FlattenOptions,
+) -> Result<Vec<(Path, Value)>, Error> {
+    let mut walker = Walker::new(input, options);
+    std::iter::from_fn(|| walker.next(|walker| Path::from_iter(walker.segments.clone()))).collect()
+}
+
+/// The borrowing version of [`try_flatten_paths_with()`].
+pub(crate) fn try_flatten_ref_paths_with<'a>(
+    input: &'a Value,
+    options: &FlattenOptions,
+) -> Result<Vec<(Path, &'a Value)>, Error> {
+    let mut walker = Walker::new(input, options);
+    std::iter::from_fn(|| walker.next(|walker| Path::from_iter(walker.segments.clone()))).collect()
+}
+
+/// The borrowing version of [`try_flatten_paths_with()`], where the empty
+/// mappings and a root that is not flattened are leaves as well, the latter
+/// with the empty path.
+pub(crate) fn try_flatten_ref_all_paths_with<'a>(
+    input: &'a Value,
+    options: &FlattenOptions,
+) -> Result<Vec<(Path, &'a Value)>, Error> {
+    let mut walker = Walker::new(input, options).all_leaves();
+    std::iter::from_fn(|| walker.next(|walker| Path::from_iter(walker.segments.clone()))).collect()
+}
+
+/// Flattens the `input` YAML value into both the joined keys and their
+/// [`Path`]s, keeping the order of the keys in the document.
+pub(crate) fn try_flatten_keyed_with(
+    input: Value,
+    options: &FlattenOptions,
+) -> Result<Vec<(String, Path, Value)>, Error> {
+    let mut walker = Walker::new(input, options);
+    let key_and_path = |walker: &Walker<Value>| {
+        let path = Path::from_iter(walker.segments.clone());
+        (walker.joined_path(), path)
+    };
+    std::iter::from_fn(|| walker.next(key_and_path))
+        .map(|leaf| leaf.map(|((key, path), value)| (key, path, value)))
+        .collect()
+}
+
+/// Looks up the value that would be flattened to `key`, whether the tokens of
+/// `key` are nested or written together as dotted mapping keys, e.g.,
+/// `cluster.routing.enable` is found in both `cluster: {routing.enable: x}`
+/// and `cluster.routing: {enable: x}`.
+///
+/// This is equivalent to calling [`lookup_with()`] with the default
+/// [`FlattenOptions`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::lookup;
+/// let value: Value = from_str(
+///     r#"
+/// cluster.name: es
+/// cluster:
+///   routing:
+///     allocation.enable: all
+/// "#,
+/// )
+/// .unwrap();
+///
+/// assert_eq!(
+///     lookup(&value, "cluster.routing.allocation.enable"),
+///     Ok(Some(&Value::String("all".into())))
+/// );
+/// assert_eq!(
+///     lookup(&value, "cluster.name"),
+///     Ok(Some(&Value::String("es".into())))
+/// );
+/// ```
+pub fn lookup<'a>(input: &'a Value, key: &str) -> Result<Option<&'a Value>, Error> {
+    lookup_with(input, key, &FlattenOptions::default())
+}
+
+/// Looks up the value in `input` that [`flatten_with()`] would store under
+/// `key`, or the mapping or sequence whose values would be stored under keys
+/// starting with `key`, without flattening the whole `input`.
+///
+/// Only the values at exactly `key` are looked up, keys under it that are
+/// written in other forms are not merged into the returned value.
+///
+/// # Errors
+///
+/// Returns [`Error::DuplicateValue`] if more than one value is found at
+/// `key`, e.g., both `a.b: 1` and `a: {b: 2}` are given. The `key` and
+/// `prior` of the error are the paths of the values in the canonical form of
+/// [`Path`], which tells the forms apart.
+pub fn lookup_with<'a>(
+    input: &'a Value,
+    key: &str,
+    options: &FlattenOptions,
+) -> Result<Option<&'a Value>, Error> {
+    let separator = options.separator.as_str();
+    let mut found: Option<(Path, &Value)> = None;
+    // Only the values whose paths are `key` or prefixes of it are visited.
+    let mut stack = vec![(String::new(), Path::new(), input)];
+    while let Some((path, segments, value)) = stack.pop() {
+        if path == key && !segments.is_empty() {
+            if let Some((prior, _)) = &found {
+                return Err(Error::DuplicateValue {
+                    key: segments.to_string(),
+                    token: segments.iter().last().expect("not empty").to_string(),
+                    prior: prior.to_string(),
+                });
+            }
+            found = Some((segments, value));
+            continue;
+        }
+
+        let children: Vec<(PathSegment, String, &Value)> = match value.unpack(options) {
+            Unpacked::Leaf(_) => continue,
+            Unpacked::Sequence(sequence) => sequence
+                .map(|(index, value)| (PathSegment::Index(index), index.to_string(), value))
+                .collect(),
+            Unpacked::Mapping(mapping) => mapping
+                .filter_map(|(key, value)| {
+                    // Such keys cannot be flattened, so nothing is stored
+                    // under them.
+                    let token = key.into_token(String::new).ok()?;
+                    let escaped = escape_token(&token, key.is_string(), options);
+                    Some((PathSegment::Key(token), escaped, value))
+                })
+                .collect(),
+        };
+
+        let mut visits = Vec::new();
+        for (segment, token, value) in children {
+            let child_path = if segments.is_empty() {
+                token
+            } else {
+                format!("{}{}{}", path, separator, token)
+            };
+            let is_prefix = key
+                .strip_prefix(child_path.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(separator));
+            if is_prefix {
+                let mut child_segments = segments.clone();
+                child_segments.push(segment);
+                visits.push((child_path, child_segments, value));
+            }
+        }
+        // Reversed so that they are visited in the order of the document.
+        stack.extend(visits.into_iter().rev());
+    }
+
+    Ok(found.map(|(_, value)| value))
+}
+
+/// A YAML value that can be flattened, implemented for both owned and
+/// borrowed values so that they share [`Walker`].
+trait Flattenable: Sized {
+    type Sequence: Iterator<Item = Self>;
+    type Mapping: Iterator<Item = (Self, Self)>;
+
+    /// Splits `self` into its entries, or returns it as a leaf if it should
+    /// not be flattened.
+    fn unpack(self, options: &FlattenOptions) -> Unpacked<Self>;
+
+    /// Converts `self`, a mapping key, to a path token, see [`key_to_token()`].
+    fn into_token(self, path: impl FnOnce() -> String) -> Result<String, Error>;
 
-    output
+    fn is_string(&self) -> bool;
+
+    fn is_empty_mapping(&self) -> bool;
+}
+
+/// What [`Flattenable::unpack()`] returns.
+enum Unpacked<V: Flattenable> {
+    Leaf(V),
+    Sequence(Enumerate<V::Sequence>),
+    Mapping(V::Mapping),
 }
 
-/// Inner helper function to do the recursive flatten job.
-fn _flatten(output: &mut BTreeMap<String, Value>, path: &mut Vec<String>, input: Value) {
-    match input {
-        Value::Null | Value::Bool(_) | Value::Number(_) | Value::String(_) => {
-            if !path.is_empty() {
-                let full_path = path.join(DOT);
-                output.insert(full_path, input);
+impl Flattenable for Value {
+    type Mapping = serde_yaml_ng::mapping::IntoIter;
+    type Sequence = std::vec::IntoIter<Value>;
+
+    fn unpack(self, options: &FlattenOptions) -> Unpacked<Self> {
+        match self {
+            Value::Sequence(sequence) if options.flatten_sequences && !sequence.is_empty() => {
+                Unpacked::Sequence(sequence.into_iter().enumerate())
             }
+            Value::Mapping(mapping) => Unpacked::Mapping(mapping.into_iter()),
+            // A tagged value is treated as a whole, i.e., what is inside of it
+            // won't be flattened, so that its tag is preserved.
+            leaf => Unpacked::Leaf(leaf),
         }
+    }
+
+    fn into_token(self, path: impl FnOnce() -> String) -> Result<String, Error> {
+        key_to_token(self, path)
+    }
 
-        Value::Tagged(_) => unimplemented!(),
+    fn is_string(&self) -> bool {
+        Value::is_string(self)
+    }
+
+    fn is_empty_mapping(&self) -> bool {
+        self.as_mapping().is_some_and(Mapping::is_empty)
+    }
+}
 
-        Value::Sequence(_) => {
-            // Let's do not flatten array for now.
-            if !path.is_empty() {
-                let full_path = path.join(DOT);
-                output.insert(full_path, input);
+impl<'a> Flattenable for &'a Value {
+    type Mapping = serde_yaml_ng::mapping::Iter<'a>;
+    type Sequence = std::slice::Iter<'a, Value>;
+
+    fn unpack(self, options: &FlattenOptions) -> Unpacked<Self> {
+        match self {
+            Value::Sequence(sequence) if options.flatten_sequences && !sequence.is_empty() => {
+                Unpacked::Sequence(sequence.iter().enumerate())
             }
+            Value::Mapping(mapping) => Unpacked::Mapping(mapping.iter()),
+            leaf => Unpacked::Leaf(leaf),
         }
+    }
+
+    fn into_token(self, path: impl FnOnce() -> String) -> Result<String, Error> {
+        // Only the key is cloned, which is usually a short string.
+        key_to_token(self.clone(), path)
+    }
 
-        Value::Mapping(mapping) => {
-            for (key, value) in mapping {
-                let key = match key {
-                    Value::Null => unreachable!("a mapping key cannot be NULL"),
-                    Value::Bool(boolean) => boolean.to_string(),
-                    Value::Number(number) => number.to_string(),
-                    Value::String(string) => string,
+    fn is_string(&self) -> bool {
+        Value::is_string(self)
+    }
 
-                    non_literal => {
-                        unreachable!("a mapping key should be listeral, found: {:?}", non_literal)
-                    }
-                };
-                path.push(key);
+    fn is_empty_mapping(&self) -> bool {
+        self.as_mapping().is_some_and(Mapping::is_empty)
+    }
+}
+
+/// A lazy iterator that flattens a YAML value, yielding one `(path, leaf)`
+/// pair at a time in the order of the document.
+///
+/// It walks the value with an explicit stack rather than recursion, so deeply
+/// nested input won't overflow the stack, see also
+/// [`FlattenOptions::max_depth()`]. After an error is yielded, the iteration
+/// stops.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::Flatten;
+/// # use serde_yaml_nested::conversion::FlattenOptions;
+/// let nested: Value = from_str("{a: {b: 1, c: 2}, d: 3}").unwrap();
+///
+/// let mut flatten = Flatten::new(nested, &FlattenOptions::new());
+/// assert_eq!(
+///     flatten.next(),
+///     Some(Ok((String::from("a.b"), Value::Number(1.into()))))
+/// );
+/// ```
+pub struct Flatten {
+    walker: Walker<Value>,
+}
+
+impl Flatten {
+    /// Creates an iterator that flattens `input` according to `options`.
+    pub fn new(input: Value, options: &FlattenOptions) -> Self {
+        Self {
+            walker: Walker::new(input, options),
+        }
+    }
+}
+
+impl Iterator for Flatten {
+    type Item = Result<(String, Value), Error>;
 
-                _flatten(output, path, value);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walker.next(Walker::joined_path)
+    }
+}
+
+impl std::iter::FusedIterator for Flatten {}
+
+/// The borrowing version of [`Flatten`], the leaves are borrowed from the
+/// input value.
+pub struct FlattenRef<'a> {
+    walker: Walker<&'a Value>,
+}
+
+impl<'a> FlattenRef<'a> {
+    /// Creates an iterator that flattens `input` according to `options`.
+    pub fn new(input: &'a Value, options: &FlattenOptions) -> Self {
+        Self {
+            walker: Walker::new(input, options),
+        }
+    }
+}
+
+impl<'a> Iterator for FlattenRef<'a> {
+    type Item = Result<(String, &'a Value), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.walker.next(Walker::joined_path)
+    }
+}
+
+impl std::iter::FusedIterator for FlattenRef<'_> {}
+
+/// The shared implementation of [`Flatten`] and [`FlattenRef`].
+struct Walker<V: Flattenable> {
+    options: FlattenOptions,
+    /// The value to start with, taken on the first call of `next()`.
+    root: Option<V>,
+    /// The sequences and mappings being walked, the innermost last.
+    stack: Vec<Unpacked<V>>,
+    /// The tokens leading to the innermost container, the root has no token.
+    path: Vec<String>,
+    /// The segments of `path`, before being escaped and joined.
+    segments: Vec<PathSegment>,
+    /// Whether the empty mappings and a root that is not flattened are
+    /// leaves as well, see [`Walker::all_leaves()`].
+    all_leaves: bool,
+}
 
-                path.pop();
+impl<V: Flattenable> Walker<V> {
+    fn new(input: V, options: &FlattenOptions) -> Self {
+        Self {
+            options: options.clone(),
+            root: Some(input),
+            stack: Vec::new(),
+            path: Vec::new(),
+            segments: Vec::new(),
+            all_leaves: false,
+        }
+    }
+
+    /// Makes the walker yield the empty mappings, and a root that is not
+    /// flattened with the empty path, so that no value of the input is
+    /// left out.
+    fn all_leaves(mut self) -> Self {
+        self.all_leaves = true;
+        self
+    }
+
+    /// Returns the next leaf, whose path is built by `path` from the walker.
+    fn next<P>(&mut self, path: impl FnOnce(&Self) -> P) -> Option<Result<(P, V), Error>> {
+        if let Some(root) = self.root.take() {
+            match root.unpack(&self.options) {
+                Unpacked::Leaf(leaf) if self.all_leaves => return Some(Ok((path(self), leaf))),
+                // There is no path for a root leaf.
+                Unpacked::Leaf(_) => return None,
+                container => self.stack.push(container),
+            }
+        }
+
+        loop {
+            let (segment, token, value) = match self.stack.last_mut()? {
+                Unpacked::Leaf(_) => unreachable!("leaves are never pushed to the stack"),
+                Unpacked::Sequence(sequence) => match sequence.next() {
+                    Some((index, value)) => (PathSegment::Index(index), index.to_string(), value),
+                    None => {
+                        self.stack.pop();
+                        self.path.pop();
+                        self.segments.pop();
+                        continue;
+                    }
+                },
+                Unpacked::Mapping(mapping) => match mapping.next() {
+                    Some((key, value)) => match self.key_to_token(key) {
+                        Ok((segment, token)) => (segment, token, value),
+                        Err(error) => {
+                            self.stack.clear();
+                            return Some(Err(error));
+                        }
+                    },
+                    None => {
+                        self.stack.pop();
+                        self.path.pop();
+                        self.segments.pop();
+                        continue;
+                    }
+                },
+            };
+
+            self.path.push(token);
+            self.segments.push(segment);
+            if let Some(limit) = self
+                .options
+                .max_depth
+                .filter(|&limit| self.path.len() > limit)
+            {
+                self.stack.clear();
+                return Some(Err(Error::DepthExceeded {
+                    path: self.joined_path(),
+                    limit,
+                }));
+            }
+            let unpacked = match self.all_leaves && value.is_empty_mapping() {
+                true => Unpacked::Leaf(value),
+                false => value.unpack(&self.options),
+            };
+            match unpacked {
+                Unpacked::Leaf(leaf) => {
+                    let full_path = path(self);
+                    self.path.pop();
+                    self.segments.pop();
+                    return Some(Ok((full_path, leaf)));
+                }
+                container => self.stack.push(container),
             }
         }
     }
+
+    /// Converts a mapping `key` to its segment and its escaped token.
+    fn key_to_token(&self, key: V) -> Result<(PathSegment, String), Error> {
+        let is_string = key.is_string();
+        let key = key.into_token(|| self.joined_path())?;
+        let token = escape_token(&key, is_string, &self.options);
+
+        Ok((PathSegment::Key(key), token))
+    }
+
+    /// Joins the tokens of the current path.
+    fn joined_path(&self) -> String {
+        self.path.join(&self.options.separator)
+    }
+}
+
+/// Escapes the `key` token if [`FlattenOptions::escaping()`] is enabled,
+/// `is_string` tells whether the key is originally a string.
+fn escape_token(key: &str, is_string: bool, options: &FlattenOptions) -> String {
+    if options.escaping {
+        let mark_string = is_string && infer_key_type(key).is_some();
+        escape_key(key, &options.separator, mark_string)
+    } else {
+        key.to_string()
+    }
+}
+
+/// Converts a mapping `key` to a path token, `path` gives where the mapping
+/// is, which is used in the errors.
+pub(crate) fn key_to_token(key: Value, path: impl FnOnce() -> String) -> Result<String, Error> {
+    match key {
+        Value::Bool(boolean) => Ok(boolean.to_string()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::String(string) => Ok(string),
+
+        Value::Tagged(tagged) => Err(Error::UnsupportedTag {
+            path: path(),
+            tag: tagged.tag.to_string(),
+        }),
+        unsupported => Err(Error::UnsupportedKey {
+            path: path(),
+            key: unsupported,
+        }),
+    }
 }
 
 /// The errors that may happen during conversion.
 #[derive(Debug, PartialEq, Clone)]
 pub enum Error {
-    DuplicateValue { key: String, token: String },
+    /// While handling `key`, `token` is found to have 2 values, the other
+    /// value is set by the `prior` key.
+    DuplicateValue {
+        key: String,
+        token: String,
+        prior: String,
+    },
+    /// A mapping key under `path` cannot be flattened, only string, boolean
+    /// and number keys are supported.
+    UnsupportedKey { path: String, key: Value },
+    /// A mapping key under `path` is tagged with `tag`, tagged keys cannot be
+    /// flattened.
+    UnsupportedTag { path: String, tag: String },
+    /// All the errors found with [`ConflictPolicy::CollectAll`], in the
+    /// order they are found.
+    Conflicts(Vec<Error>),
+    /// The tokens under `path` cannot be turned into a sequence, see
+    /// [`UnflattenOptions::sequences()`].
+    InvalidSequence {
+        path: String,
+        kind: SequenceErrorKind,
+    },
+    /// `path` is nested deeper than `limit`, see
+    /// [`FlattenOptions::max_depth()`] and [`UnflattenOptions::max_depth()`].
+    DepthExceeded { path: String, limit: usize },
+    /// There is no value at `path` to be changed.
+    PathNotFound { path: String },
+    /// `pointer` is not a valid JSON Pointer as defined in RFC 6901.
+    InvalidPointer { pointer: String },
+    /// The `index`th operation of a JSON Patch is invalid, see `reason`.
+    InvalidPatch { index: usize, reason: String },
+    /// The value at `path` is not the one expected by a JSON Patch `test`
+    /// operation.
+    TestFailed { path: String },
+}
+
+/// Why the tokens under a path cannot be turned into a sequence.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SequenceErrorKind {
+    /// Indexes `0..len` should all be present, but `index` is missing, e.g.,
+    /// `a.0` and `a.2` are given without `a.1`.
+    MissingIndex { index: usize },
+    /// Indexes and names are mixed as siblings, e.g., `a.0` and `a.b`.
+    MixedTokens { index: String, name: String },
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::DuplicateValue { key, token } => {
+            Self::DuplicateValue { key, token, prior } => {
                 write!(
                     f,
-                    "while handling key '{}', found a token '{}' that has at least 2 values",
-                    key, token
+                    "while handling key '{}', found a token '{}' that has at least 2 values, \
+                     the other value is from key '{}'",
+                    key, token, prior
                 )
             }
-        }
+            Self::UnsupportedKey { path, key } => {
+                write!(
+                    f,
+                    "under path '{}', found a key '{:?}' that cannot be flattened",
+                    path, key
+                )
+            }
+            Self::UnsupportedTag { path, tag } => {
+                write!(
+                    f,
+                    "under path '{}', found a key tagged with '{}' that cannot be flattened",
+                    path, tag
+                )
+            }
+            Self::Conflicts(conflicts) => {
+                write!(f, "found {} conflicts", conflicts.len())?;
+                for conflict in conflicts {
+                    write!(f, "\n  {}", conflict)?;
+                }
+                Ok(())
+            }
+            Self::InvalidSequence { path, kind } => match kind {
+                SequenceErrorKind::MissingIndex { index } => {
+                    write!(f, "sequence '{}' does not have index '{}'", path, index)
+                }
+                SequenceErrorKind::MixedTokens { index, name } => {
+                    write!(
+                        f,
+                        "sequence '{}' has both index '{}' and name '{}' as its tokens",
+                        path, index, name
+                    )
+                }
+            },
+            Self::DepthExceeded { path, limit } => {
+                write!(
+                    f,
+                    "path '{}' is nested deeper than the limit {}",
+                    path, limit
+                )
+            }
+            Self::PathNotFound { path } => write!(f, "path '{}' is not found", path),
+            Self::InvalidPointer { pointer } => {
+                write!(f, "'{}' is not a valid JSON Pointer", pointer)
+            }
+            Self::InvalidPatch { index, reason } => {
+                write!(
+                    f,
+                    "operation {} of the JSON Patch is invalid: {}",
+                    index, reason
+                )
+            }
+            Self::TestFailed { path } => {
+                write!(f, "the value at '{}' is not the expected one", path)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Options to customize the behavior of [`unflatten_with()`].
+///
+/// The default options make [`unflatten_with()`] behave exactly like
+/// [`unflatten()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnflattenOptions {
+    separator: String,
+    escaping: bool,
+    sequences: bool,
+    infer_key_types: bool,
+    infer_value_types: bool,
+    merge_mappings: bool,
+    on_conflict: ConflictPolicy,
+    max_depth: Option<usize>,
+}
+
+impl Default for UnflattenOptions {
+    fn default() -> Self {
+        Self {
+            separator: DOT.to_string(),
+            escaping: false,
+            sequences: false,
+            infer_key_types: false,
+            infer_value_types: false,
+            merge_mappings: false,
+            on_conflict: ConflictPolicy::default(),
+            max_depth: None,
+        }
+    }
+}
+
+impl UnflattenOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the separator used to split a key into tokens, e.g., `/`, `__`
+    /// or `::`.
+    ///
+    /// Default to `.`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `separator` is empty.
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        assert!(!self.separator.is_empty(), "separator cannot be empty");
+        self
+    }
+
+    /// If set to `true`, a backslash escapes the separator or the character
+    /// following it, e.g., `cluster.node\.attr` becomes
+    /// `cluster: {node.attr: ...}`. This is the reverse of
+    /// [`FlattenOptions::escaping()`].
+    ///
+    /// A token containing a redundant escape, i.e., one that escapes neither
+    /// a separator nor a backslash, e.g., `\0`, is always a string key, it
+    /// is never treated as a sequence index or inferred as another type. A
+    /// backslash at the end of the key escapes nothing and is dropped, e.g.,
+    /// a lone `\` is a single empty key.
+    ///
+    /// Default to `false`, in which case backslashes are normal characters.
+    pub fn escaping(mut self, escaping: bool) -> Self {
+        self.escaping = escaping;
+        self
+    }
+
+    /// If set to `true`, tokens that are sequence indexes (`0`, `1`, ...,
+    /// without leading zeros) will be turned into sequences rather than
+    /// mappings, e.g., `a.0: x` and `a.1: y` become `a: [x, y]`. This is the
+    /// reverse of [`FlattenOptions::flatten_sequences()`].
+    ///
+    /// Indexes under the same path are not required to be given in order, but
+    /// they should be contiguous and start from `0`, and they cannot be mixed
+    /// with other tokens, otherwise [`Error::InvalidSequence`] will be returned.
+    ///
+    /// Default to `false`, in which case every token becomes a mapping key.
+    pub fn sequences(mut self, sequences: bool) -> Self {
+        self.sequences = sequences;
+        self
+    }
+
+    /// If set to `true`, mapping keys will be restored to booleans or numbers
+    /// if they are exactly how [`flatten()`] writes such keys, e.g., `1.a: x`
+    /// becomes `1: {a: x}` where `1` is a number, rather than `"1": {a: x}`.
+    ///
+    /// Tokens containing redundant escapes are always strings, see
+    /// [`Self::escaping()`].
+    ///
+    /// Default to `false`, in which case every mapping key is a string.
+    pub fn infer_key_types(mut self, infer_key_types: bool) -> Self {
+        self.infer_key_types = infer_key_types;
+        self
+    }
+
+    /// If set to `true`, string values are resolved with the YAML 1.2 core
+    /// schema, the same as a plain scalar in a YAML file, see
+    /// [`infer_scalar()`]. Flat sources like environment variables or
+    /// `.properties` files only give strings, e.g., `a.b: "128"` becomes
+    /// `a: {b: 128}`.
+    ///
+    /// Only the values given in the input are resolved, the strings nested in
+    /// sequence or mapping values are not.
+    ///
+    /// Default to `false`, in which case values are kept as they are.
+    pub fn infer_value_types(mut self, infer_value_types: bool) -> Self {
+        self.infer_value_types = infer_value_types;
+        self
+    }
+
+    /// If set to `true`, mapping values will be deep merged into the
+    /// existing subtrees rather than inserted as they are, so that partially
+    /// flattened input like `a: {b: 1}` and `a.c: 2` becomes
+    /// `a: {b: 1, c: 2}`, whichever comes first. Keys of the mapping values
+    /// are split as well, the same as the input keys.
+    ///
+    /// Two values still conflict if neither of them is a mapping, e.g.,
+    /// `a: {b: 1}` and `a.b: 2`.
+    ///
+    /// Tagged mappings are not merged, they are inserted as they are.
+    ///
+    /// Default to `false`, in which case a mapping value is inserted as it is
+    /// and later keys can only go into it if they come after it.
+    pub fn merge_mappings(mut self, merge_mappings: bool) -> Self {
+        self.merge_mappings = merge_mappings;
+        self
+    }
+
+    /// Sets what to do when 2 keys conflict, e.g., `a.b` is given twice, or
+    /// both `a.b` and `a.b.c` are given.
+    ///
+    /// Default to [`ConflictPolicy::Error`].
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+
+    /// Returns the policy set by [`Self::on_conflict()`].
+    pub(crate) fn conflict_policy(&self) -> ConflictPolicy {
+        self.on_conflict
+    }
+
+    /// Sets the maximum number of tokens in a key, keys with more tokens
+    /// lead to [`Error::DepthExceeded`], e.g., with a limit of `2`, `a.b` is
+    /// accepted but `a.b.c` is not. The keys of mapping values count as well
+    /// if they are merged, see [`Self::merge_mappings()`].
+    ///
+    /// Default to no limit.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+}
+
+/// What [`unflatten_with()`] does when 2 keys conflict, see
+/// [`UnflattenOptions::on_conflict()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Returns the first conflict as [`Error::DuplicateValue`].
+    #[default]
+    Error,
+    /// Keeps the value that comes first, later conflicting keys are ignored.
+    FirstWins,
+    /// Keeps the value that comes last, it replaces what the earlier
+    /// conflicting keys have set, e.g., `a.b: 1` then `a.b.c: 2` gives
+    /// `a: {b: {c: 2}}`.
+    LastWins,
+    /// Keeps the value that comes first like [`Self::FirstWins`], but rather
+    /// than stopping at the first error, the whole input is validated and all
+    /// the errors found, including the invalid sequences and the exceeded
+    /// depths, are returned in [`Error::Conflicts`] at the end.
+    CollectAll,
+}
+
+/// Unflattens the given `input` YAML.
+///
+/// This is equivalent to calling [`unflatten_with()`] with the default
+/// [`UnflattenOptions`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::unflatten;
+/// let flattened: Value = from_str(r#"a.b.c: null"#).unwrap();
+/// let mapping = match flattened {
+///     Value::Mapping(mapping) => mapping,
+///     _ => unreachable!(),
+/// };
+///
+/// let nested = unflatten(
+///     mapping
+///         .into_iter()
+///         .map(|(key, value)| (key.as_str().unwrap().to_string(), value)),
+/// )
+/// .unwrap();
+///
+/// let expected: Value = from_str(
+///     r#"
+/// a:
+///   b:
+///     c: null"#,
+/// )
+/// .unwrap();
+/// assert_eq!(nested, expected);
+/// ```
+pub fn unflatten<I: IntoIterator<Item = (String, Value)>>(input: I) -> Result<Value, Error> {
+    unflatten_with(input, &UnflattenOptions::default())
+}
+
+/// Unflattens the given `input` YAML according to the given `options`.
+///
+/// The keys of the resulting mappings are in the order they first appear in
+/// `input`, use [`flatten_ordered()`] to keep the document order during a
+/// round trip.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::unflatten_with;
+/// # use serde_yaml_nested::conversion::UnflattenOptions;
+/// let nested = unflatten_with(
+///     [
+///         (String::from("a.1"), Value::String("y".into())),
+///         (String::from("a.0"), Value::String("x".into())),
+///     ],
+///     &UnflattenOptions::new().sequences(true),
+/// )
+/// .unwrap();
+///
+/// let expected: Value = from_str("a: [x, y]").unwrap();
+/// assert_eq!(nested, expected);
+/// ```
+pub fn unflatten_with<I: IntoIterator<Item = (String, Value)>>(
+    input: I,
+    options: &UnflattenOptions,
+) -> Result<Value, Error> {
+    unflatten_segments(parse_keys(input, options), options)
+}
+
+/// Unflattens the given `input` keyed by [`Path`]s according to the given
+/// `options`, so that no key has to be split.
+///
+/// Index segments always become sequence indexes, whatever
+/// [`UnflattenOptions::sequences()`] is, and key segments are inferred as
+/// other types if [`UnflattenOptions::infer_key_types()`] is enabled. The
+/// separator and escaping settings are only used to write the paths in the
+/// errors.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::unflatten_paths_with;
+/// # use serde_yaml_nested::conversion::UnflattenOptions;
+/// # use serde_yaml_nested::path::Path;
+/// let nested = unflatten_paths_with(
+///     [(Path::parse(r"a.b\.c.0"), Value::String("x".into()))],
+///     &UnflattenOptions::new(),
+/// )
+/// .unwrap();
+///
+/// let expected: Value = from_str("a: {b.c: [x]}").unwrap();
+/// assert_eq!(nested, expected);
+/// ```
+pub fn unflatten_paths_with<I: IntoIterator<Item = (Path, Value)>>(
+    input: I,
+    options: &UnflattenOptions,
+) -> Result<Value, Error> {
+    let input = input.into_iter().map(|(path, value)| {
+        let segments: Vec<Segment> = path
+            .into_iter()
+            .map(|segment| match segment {
+                PathSegment::Key(key) => match infer_key_type(&key) {
+                    Some(key) if options.infer_key_types => Segment::Key(key),
+                    _ => Segment::Key(Value::String(key)),
+                },
+                PathSegment::Index(index) => Segment::Index(index),
+            })
+            .collect();
+        (join_path(&segments, options), segments, value)
+    });
+
+    unflatten_segments(input, options)
+}
+
+/// Normalizes a document that mixes dotted keys and nested mappings into
+/// the fully nested form, e.g., `{a.b: 1, a: {c: 2}}` becomes
+/// `{a: {b: 1, c: 2}}`.
+///
+/// The dotted string keys are split the same way as [`unflatten()`] does,
+/// while boolean and number keys keep their types. The keys are kept in the
+/// order of the document, and empty mappings are kept as they are, or merged
+/// with the other keys under them, e.g., `{a: {}, a.b: 1}` becomes
+/// `{a: {b: 1}}`. An `input` that is not a mapping is returned as it is.
+///
+/// # Errors
+///
+/// Returns [`Error::DuplicateValue`] if the two forms set the same key, e.g.,
+/// `{a.b: 1, a: {b: 2}}`, or one sets a key under the value set by the other,
+/// e.g., `{a.b: 1, a: {b: {c: 2}}}`. Keys that cannot be flattened lead to
+/// the same errors as [`try_flatten()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::normalize_nested;
+/// let mixed: Value = from_str("{cluster.name: es, cluster: {routing.enable: all}}").unwrap();
+///
+/// let expected: Value = from_str("cluster: {name: es, routing: {enable: all}}").unwrap();
+/// assert_eq!(normalize_nested(mixed).unwrap(), expected);
+/// ```
+pub fn normalize_nested(input: Value) -> Result<Value, Error> {
+    let Value::Mapping(mapping) = input else {
+        return Ok(input);
+    };
+
+    let options = UnflattenOptions::default();
+    let mut errors = Errors::new(&options);
+    let mut root = Branch::default();
+    for (segments, value) in split_leaves(mapping)? {
+        let key = join_path(&segments, &options);
+        let node = match value {
+            Value::Mapping(mapping) if mapping.is_empty() => Node::Branch(Branch::default()),
+            value => Node::Leaf(value),
+        };
+        root.insert(&key, &segments, node, &options, &mut errors)?;
+    }
+
+    root.into_value(&mut Vec::new(), &options, &mut errors)
+}
+
+/// Normalizes a document that mixes dotted keys and nested mappings into a
+/// single-level mapping of dotted keys, e.g., `{a.b: 1, a: {c: 2}}` becomes
+/// `{a.b: 1, a.c: 2}`.
+///
+/// The keys are in the order they first appear in the document, and the
+/// conflicts are reported the same way as [`normalize_nested()`]. Empty
+/// mappings are kept as values, e.g., `{a: {b: {}}}` becomes `{a.b: {}}`,
+/// and keys that are not joined with others keep their types. An `input`
+/// that is not a mapping is returned as it is.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::normalize_flat;
+/// let mixed: Value = from_str("{cluster.name: es, cluster: {routing.enable: all}}").unwrap();
+///
+/// let expected: Value = from_str("{cluster.name: es, cluster.routing.enable: all}").unwrap();
+/// assert_eq!(normalize_flat(mixed).unwrap(), expected);
+/// ```
+pub fn normalize_flat(input: Value) -> Result<Value, Error> {
+    if !input.is_mapping() {
+        return Ok(input);
+    }
+
+    // Normalizing first finds the conflicts, and the nested keys are split.
+    let Value::Mapping(nested) = normalize_nested(input)? else {
+        unreachable!("a mapping is normalized into a mapping")
+    };
+    let options = UnflattenOptions::default();
+    let mut flat = Mapping::new();
+    for (mut segments, value) in split_leaves(nested)? {
+        let key = join_path(&segments, &options);
+        let flat_key = match segments.pop() {
+            Some(Segment::Key(single)) if segments.is_empty() => single,
+            _ => Value::String(key.clone()),
+        };
+        if flat.contains_key(&flat_key) {
+            // e.g., `1.a` is given by both `1: {a: x}` and `"1": {a: y}`
+            return Err(Error::DuplicateValue {
+                token: key.rsplit(DOT).next().unwrap_or_default().to_string(),
+                prior: key.clone(),
+                key,
+            });
+        }
+        flat.insert(flat_key, value);
+    }
+
+    Ok(Value::Mapping(flat))
+}
+
+/// Splits `mapping` into its leaves in document order, with the segments
+/// leading to them, see [`normalize_nested()`].
+///
+/// String keys are split on dots, boolean and number keys are kept as they
+/// are, and empty mappings are leaves. The nested mappings are walked with an
+/// explicit stack rather than recursion.
+fn split_leaves(mapping: Mapping) -> Result<Vec<(Vec<Segment>, Value)>, Error> {
+    let options = UnflattenOptions::default();
+    let mut leaves = Vec::new();
+    let mut stack = vec![(Vec::new(), mapping.into_iter())];
+    while let Some((segments, entries)) = stack.last_mut() {
+        let Some((key, value)) = entries.next() else {
+            stack.pop();
+            continue;
+        };
+
+        let mut sub_segments = segments.clone();
+        match key {
+            Value::String(key) => sub_segments.extend(parse_key(&key, &options)),
+            key => {
+                // Only booleans and numbers are supported
+                key_to_token(key.clone(), || join_path(segments, &options))?;
+                sub_segments.push(Segment::Key(key));
+            }
+        }
+        match value {
+            Value::Mapping(mapping) if !mapping.is_empty() => {
+                stack.push((sub_segments, mapping.into_iter()));
+            }
+            value => leaves.push((sub_segments, value)),
+        }
+    }
+
+    Ok(leaves)
+}
+
+/// Splits the keys of `input`, see [`parse_key()`].
+fn parse_keys<'a, I>(
+    input: I,
+    options: &'a UnflattenOptions,
+) -> impl Iterator<Item = (String, Vec<Segment>, Value)> + 'a
+where
+    I: IntoIterator<Item = (String, Value)>,
+    I::IntoIter: 'a,
+{
+    input.into_iter().map(|(key, value)| {
+        let segments = parse_key(&key, options);
+        (key, segments, value)
+    })
+}
+
+/// The shared implementation of [`unflatten_with()`] and
+/// [`unflatten_paths_with()`], the keys are already split into segments.
+fn unflatten_segments<I: IntoIterator<Item = (String, Vec<Segment>, Value)>>(
+    input: I,
+    options: &UnflattenOptions,
+) -> Result<Value, Error> {
+    let mut errors = Errors::new(options);
+    let value = build_tree(input, options, &mut errors)?.into_value(
+        &mut Vec::new(),
+        options,
+        &mut errors,
+    )?;
+    if !errors.errors.is_empty() {
+        return Err(Error::Conflicts(errors.errors));
+    }
+
+    Ok(value)
+}
+
+/// Builds the intermediate tree from the `input` keys, their segments and
+/// values.
+fn build_tree<I: IntoIterator<Item = (String, Vec<Segment>, Value)>>(
+    input: I,
+    options: &UnflattenOptions,
+    errors: &mut Errors,
+) -> Result<Branch, Error> {
+    let mut root = Branch::default();
+    for (key, segments, value) in input {
+        let value = match value {
+            Value::String(string) if options.infer_value_types => {
+                infer_scalar(&string).unwrap_or(Value::String(string))
+            }
+            value => value,
+        };
+        if let Err(error) = check_depth(&segments, options) {
+            errors.report(error)?;
+            continue;
+        }
+        if options.merge_mappings {
+            root.merge(key, segments, value, options, errors)?;
+        } else {
+            root.insert(&key, &segments, Node::Leaf(value), options, errors)?;
+        }
+    }
+
+    Ok(root)
+}
+
+/// Returns [`Error::DepthExceeded`] if there are more `segments` than
+/// [`UnflattenOptions::max_depth()`] allows.
+fn check_depth(segments: &[Segment], options: &UnflattenOptions) -> Result<(), Error> {
+    match options.max_depth {
+        Some(limit) if segments.len() > limit => Err(Error::DepthExceeded {
+            path: join_path(&segments[..=limit], options),
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// The errors found during unflattening.
+struct Errors {
+    /// If `true`, errors are collected rather than returned, see
+    /// [`ConflictPolicy::CollectAll`].
+    collect_all: bool,
+    errors: Vec<Error>,
+}
+
+impl Errors {
+    fn new(options: &UnflattenOptions) -> Self {
+        Self {
+            collect_all: options.on_conflict == ConflictPolicy::CollectAll,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Reports `error`, returns it back if unflattening should stop.
+    fn report(&mut self, error: Error) -> Result<(), Error> {
+        if self.collect_all {
+            self.errors.push(error);
+            Ok(())
+        } else {
+            Err(error)
+        }
+    }
+
+    /// Handles a conflict according to [`UnflattenOptions::on_conflict()`],
+    /// returns `true` if the existing value should be replaced.
+    fn resolve_conflict(
+        &mut self,
+        error: Error,
+        options: &UnflattenOptions,
+    ) -> Result<bool, Error> {
+        match options.on_conflict {
+            ConflictPolicy::Error | ConflictPolicy::CollectAll => {
+                self.report(error).map(|()| false)
+            }
+            ConflictPolicy::FirstWins => Ok(false),
+            ConflictPolicy::LastWins => Ok(true),
+        }
+    }
+}
+
+/// Inserts `value` into a mapping value given by the user, following the
+/// remaining `segments` of `key`, `prior` is the key that set the mapping and
+/// `path` contains the segments leading to it.
+#[allow(clippy::too_many_arguments)]
+fn insert_into_mapping(
+    mapping: &mut Mapping,
+    key: &str,
+    prior: &str,
+    path: &[Segment],
+    segments: &[Segment],
+    value: Value,
+    options: &UnflattenOptions,
+    errors: &mut Errors,
+) -> Result<(), Error> {
+    let mut outermost_mapping = mapping;
+    for (nth, segment) in segments.iter().enumerate() {
+        let token_str = segment.to_string();
+        let token = match segment {
+            Segment::Key(key) => key.clone(),
+            Segment::Index(_) => Value::String(token_str.clone()),
+        };
+
+        let key_is_last_key = nth + 1 == segments.len();
+
+        // We use `.get(&self)` to acquire if this key exists or not
+        // cannot use `.get_mut(&mut self)` as that will borrow
+        // `outermost_mapping` for more than once.
+        let exist = outermost_mapping.get(&token).is_some();
+
+        if exist {
+            let existing = outermost_mapping
+                .get(&token)
+                .expect("should be Some as `exist` is true");
+            if key_is_last_key || !existing.is_mapping() {
+                let error = Error::DuplicateValue {
+                    key: key.to_string(),
+                    token: token_str,
+                    prior: prior.to_string(),
+                };
+                if errors.resolve_conflict(error, options)? {
+                    let node = Node::nested(&segments[nth + 1..], key, Node::Leaf(value));
+                    let mut path = [path, &segments[..=nth]].concat();
+                    let replacement = node.into_value(&mut path, options, errors)?;
+                    outermost_mapping.insert(token, replacement);
+                }
+                break;
+            }
+
+            outermost_mapping = outermost_mapping
+                .get_mut(&token)
+                .and_then(Value::as_mapping_mut)
+                .expect("should be a mapping as there is no conflict");
+        } else if key_is_last_key {
+            outermost_mapping.insert(token, value);
+            break;
+        } else {
+            outermost_mapping.insert(token.clone(), Value::Mapping(Mapping::new()));
+            let newly_inserted_mapping = outermost_mapping
+                .get_mut(&token)
+                .unwrap()
+                .as_mapping_mut()
+                .unwrap();
+            outermost_mapping = newly_inserted_mapping;
+        }
+    }
+
+    Ok(())
+}
+
+/// A token of a flattened key, see [`parse_key()`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Segment {
+    /// A mapping key, which is a string, or a boolean or number if key types
+    /// are inferred.
+    Key(Value),
+    Index(usize),
+}
+
+impl std::fmt::Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(Value::String(key)) => write!(f, "{}", key),
+            Self::Key(Value::Bool(key)) => write!(f, "{}", key),
+            Self::Key(Value::Number(key)) => write!(f, "{}", key),
+            Self::Key(key) => unreachable!("unexpected key: {:?}", key),
+            Self::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+/// Splits `key` into segments, according to the separator, escaping and
+/// sequences settings in `options`.
+pub(crate) fn parse_key(key: &str, options: &UnflattenOptions) -> Vec<Segment> {
+    let separator = options.separator.as_str();
+    // `marked` is true if `token` contains a redundant escape, see
+    // `UnflattenOptions::escaping()`.
+    let segment = |token: String, marked: bool| {
+        if !marked {
+            if let Some(index) = parse_index(&token).filter(|_| options.sequences) {
+                return Segment::Index(index);
+            }
+            if let Some(key) = infer_key_type(&token).filter(|_| options.infer_key_types) {
+                return Segment::Key(key);
+            }
+        }
+
+        Segment::Key(Value::String(token))
+    };
+
+    if !options.escaping {
+        return key
+            .split(separator)
+            .map(|token| segment(token.to_string(), false))
+            .collect();
+    }
+
+    let mut segments = Vec::new();
+    let mut token = String::new();
+    let mut marked = false;
+    let mut rest = key;
+    loop {
+        if let Some(after_backslash) = rest.strip_prefix(BACKSLASH) {
+            if let Some(after_separator) = after_backslash.strip_prefix(separator) {
+                token.push_str(separator);
+                rest = after_separator;
+            } else if let Some(char) = after_backslash.chars().next() {
+                marked |= char != BACKSLASH;
+                token.push(char);
+                rest = &after_backslash[char.len_utf8()..];
+            } else {
+                // A trailing backslash escapes nothing, it only marks the
+                // token, e.g., a lone `\` is an empty string key.
+                marked = true;
+                rest = after_backslash;
+            }
+        } else if let Some(after_separator) = rest.strip_prefix(separator) {
+            segments.push(segment(std::mem::take(&mut token), marked));
+            marked = false;
+            rest = after_separator;
+        } else if let Some(char) = rest.chars().next() {
+            token.push(char);
+            rest = &rest[char.len_utf8()..];
+        } else {
+            segments.push(segment(token, marked));
+            return segments;
+        }
+    }
+}
+
+/// Escapes the separators and backslashes in `key`, see
+/// [`FlattenOptions::escaping()`].
+///
+/// If `mark_string` is `true`, the first character that needs no escaping
+/// will be escaped, so that `key` will always be parsed as a string key.
+pub(crate) fn escape_key(key: &str, separator: &str, mark_string: bool) -> String {
+    let mut escaped = String::with_capacity(key.len() + 1);
+    let mut marked = !mark_string;
+
+    let mut rest = key;
+    while let Some(char) = rest.chars().next() {
+        if let Some(after_separator) = rest.strip_prefix(separator) {
+            escaped.push(BACKSLASH);
+            escaped.push_str(separator);
+            rest = after_separator;
+        } else {
+            if char == BACKSLASH || !marked {
+                escaped.push(BACKSLASH);
+                marked |= char != BACKSLASH;
+            }
+            escaped.push(char);
+            rest = &rest[char.len_utf8()..];
+        }
+    }
+
+    escaped
+}
+
+/// Joins `path` into a key, the reverse of [`parse_key()`].
+fn join_path(path: &[Segment], options: &UnflattenOptions) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Key(Value::String(key)) if options.escaping => {
+                let mark_string = (options.sequences && parse_index(key).is_some())
+                    || (options.infer_key_types && infer_key_type(key).is_some());
+                escape_key(key, &options.separator, mark_string)
+            }
+            segment => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(&options.separator)
+}
+
+/// The intermediate tree built by [`unflatten_with()`].
+///
+/// Unlike building a [`Mapping`] directly, it distinguishes the nodes created
+/// from the tokens from the values given by the user, so that a value that
+/// happens to be a mapping won't be touched.
+enum Node {
+    Leaf(Value),
+    Branch(Branch),
+}
+
+/// A node whose children are keyed by segments, in insertion order.
+#[derive(Default)]
+struct Branch {
+    positions: HashMap<Segment, usize>,
+    children: Vec<Child>,
+}
+
+struct Child {
+    segment: Segment,
+    /// The input key that created this child, used in the errors.
+    key: String,
+    node: Node,
+}
+
+impl Branch {
+    fn position(&self, segment: &Segment) -> Option<usize> {
+        self.positions.get(segment).copied()
+    }
+
+    /// Inserts `node` at `segments`, creating the intermediate branches.
+    ///
+    /// Inserting an empty branch where a branch exists is a no-op, otherwise
+    /// inserting a node where a node exists is a conflict.
+    fn insert(
+        &mut self,
+        key: &str,
+        segments: &[Segment],
+        node: Node,
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<(), Error> {
+        let mut outermost_branch = self;
+        for (nth, segment) in segments.iter().enumerate() {
+            let key_is_last_key = nth + 1 == segments.len();
+
+            match outermost_branch.position(segment) {
+                Some(position) => {
+                    let existing = &outermost_branch.children[position];
+                    let is_conflict = match &existing.node {
+                        Node::Branch(_) => key_is_last_key && !node.is_empty_branch(),
+                        Node::Leaf(Value::Mapping(_)) => key_is_last_key,
+                        Node::Leaf(_) => true,
+                    };
+                    if is_conflict {
+                        let error = Error::DuplicateValue {
+                            key: key.to_string(),
+                            token: segment.to_string(),
+                            prior: existing.key.clone(),
+                        };
+                        if errors.resolve_conflict(error, options)? {
+                            let existing = &mut outermost_branch.children[position];
+                            existing.key = key.to_string();
+                            existing.node = Node::nested(&segments[nth + 1..], key, node);
+                        }
+                        break;
+                    }
+
+                    let existing = &mut outermost_branch.children[position];
+                    match &mut existing.node {
+                        // Inserting an empty branch where a branch exists
+                        Node::Branch(_) if key_is_last_key => break,
+                        Node::Branch(branch) => outermost_branch = branch,
+                        Node::Leaf(Value::Mapping(mapping)) => {
+                            let Node::Leaf(value) = node else {
+                                unreachable!("mappings are leaves only if they are not merged")
+                            };
+                            return insert_into_mapping(
+                                mapping,
+                                key,
+                                &existing.key,
+                                &segments[..=nth],
+                                &segments[nth + 1..],
+                                value,
+                                options,
+                                errors,
+                            );
+                        }
+                        Node::Leaf(_) => unreachable!("conflicts are handled above"),
+                    }
+                }
+                None if key_is_last_key => {
+                    outermost_branch.push(segment.clone(), key, node);
+                    break;
+                }
+                None => {
+                    let branch = Node::Branch(Branch::default());
+                    let position = outermost_branch.push(segment.clone(), key, branch);
+                    match &mut outermost_branch.children[position].node {
+                        Node::Branch(branch) => outermost_branch = branch,
+                        Node::Leaf(_) => unreachable!("we just inserted a branch"),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Inserts `value` at `segments`, if it is a mapping, its entries will be
+    /// merged into the branch at `segments`, see
+    /// [`UnflattenOptions::merge_mappings()`].
+    fn merge(
+        &mut self,
+        key: String,
+        segments: Vec<Segment>,
+        value: Value,
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<(), Error> {
+        let mut pending = vec![(key, segments, value)];
+        while let Some((key, segments, value)) = pending.pop() {
+            let mapping = match value {
+                Value::Mapping(mapping) => mapping,
+                value => {
+                    self.insert(&key, &segments, Node::Leaf(value), options, errors)?;
+                    continue;
+                }
+            };
+            let branch = Node::Branch(Branch::default());
+            self.insert(&key, &segments, branch, options, errors)?;
+
+            let mut entries = Vec::with_capacity(mapping.len());
+            for (sub_key, sub_value) in mapping {
+                let sub_key = match key_to_token(sub_key, || key.clone()) {
+                    Ok(sub_key) => sub_key,
+                    Err(error) => {
+                        errors.report(error)?;
+                        continue;
+                    }
+                };
+                let mut sub_segments = segments.clone();
+                sub_segments.extend(parse_key(&sub_key, options));
+                if let Err(error) = check_depth(&sub_segments, options) {
+                    errors.report(error)?;
+                    continue;
+                }
+                let full_key = format!("{}{}{}", key, options.separator, sub_key);
+                entries.push((full_key, sub_segments, sub_value));
+            }
+            // Reversed so that they are popped in order
+            pending.extend(entries.into_iter().rev());
+        }
+
+        Ok(())
+    }
+
+    /// Appends a child, returns its position.
+    fn push(&mut self, segment: Segment, key: &str, node: Node) -> usize {
+        let position = self.children.len();
+        self.positions.insert(segment.clone(), position);
+        self.children.push(Child {
+            segment,
+            key: key.to_string(),
+            node,
+        });
+
+        position
+    }
+
+    /// Converts this branch into a YAML value, `path` contains the segments
+    /// leading to it.
+    ///
+    /// The nested branches are converted with an explicit stack rather than
+    /// recursion.
+    fn into_value(
+        self,
+        path: &mut Vec<Segment>,
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<Value, Error> {
+        let mut stack = vec![Conversion::start(self, path, options, errors)?];
+        loop {
+            let conversion = stack.last_mut().expect("popped only when finished");
+            match conversion.children.next() {
+                Some(child) => {
+                    if let (Segment::Index(index), Collection::Sequence(elements)) =
+                        (&child.segment, &conversion.collection)
+                    {
+                        if *index >= elements.len() {
+                            continue; // there must be a gap, reported when finished
+                        }
+                    }
+                    match child.node {
+                        Node::Leaf(value) => conversion.add(child.segment, value),
+                        Node::Branch(branch) => {
+                            path.push(child.segment);
+                            stack.push(Conversion::start(branch, path, options, errors)?);
+                        }
+                    }
+                }
+                None => {
+                    let value = stack
+                        .pop()
+                        .expect("just checked")
+                        .finish(path, options, errors)?;
+                    match stack.last_mut() {
+                        Some(parent) => parent.add(path.pop().expect("pushed with it"), value),
+                        None => return Ok(value),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A branch being converted by [`Branch::into_value()`].
+struct Conversion {
+    collection: Collection,
+    /// The children that are not converted yet.
+    children: std::vec::IntoIter<Child>,
+}
+
+/// What a branch is converted into.
+enum Collection {
+    Sequence(Vec<Option<Value>>),
+    Mapping(Mapping),
+}
+
+impl Conversion {
+    /// Decides whether `branch` becomes a sequence or a mapping, `path`
+    /// contains the segments leading to it.
+    fn start(
+        branch: Branch,
+        path: &[Segment],
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<Self, Error> {
+        let mut first_index = None;
+        let mut first_name = None;
+        for child in &branch.children {
+            match &child.segment {
+                Segment::Index(index) => {
+                    first_index = Some(first_index.map_or(*index, |first| min(first, *index)))
+                }
+                Segment::Key(_) if first_name.is_none() => {
+                    first_name = Some(child.segment.to_string())
+                }
+                Segment::Key(_) => {}
+            }
+        }
+
+        let collection = match (first_index, first_name) {
+            (Some(_), None) => {
+                let mut elements = Vec::new();
+                elements.resize_with(branch.children.len(), || None);
+                Collection::Sequence(elements)
+            }
+            (first_index, first_name) => {
+                if let (Some(index), Some(name)) = (first_index, first_name) {
+                    errors.report(Error::InvalidSequence {
+                        path: join_path(path, options),
+                        kind: SequenceErrorKind::MixedTokens {
+                            index: index.to_string(),
+                            name,
+                        },
+                    })?;
+                    // Keep going as if it is a mapping to find the errors
+                    // under it.
+                }
+                Collection::Mapping(Mapping::new())
+            }
+        };
+
+        Ok(Self {
+            collection,
+            children: branch.children.into_iter(),
+        })
+    }
+
+    /// Adds the converted `value` of a child.
+    fn add(&mut self, segment: Segment, value: Value) {
+        match (&mut self.collection, segment) {
+            (Collection::Sequence(elements), Segment::Index(index)) => {
+                elements[index] = Some(value)
+            }
+            (Collection::Sequence(_), Segment::Key(_)) => {
+                unreachable!("this branch has no name")
+            }
+            (Collection::Mapping(mapping), Segment::Key(key)) => {
+                mapping.insert(key, value);
+            }
+            (Collection::Mapping(mapping), Segment::Index(index)) => {
+                mapping.insert(Value::Number(index.into()), value);
+            }
+        }
+    }
+
+    /// Returns the converted value once all the children are added.
+    fn finish(
+        self,
+        path: &[Segment],
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<Value, Error> {
+        match self.collection {
+            Collection::Sequence(elements) => {
+                if let Some(index) = elements.iter().position(Option::is_none) {
+                    errors.report(Error::InvalidSequence {
+                        path: join_path(path, options),
+                        kind: SequenceErrorKind::MissingIndex { index },
+                    })?;
+                }
+
+                Ok(Value::Sequence(elements.into_iter().flatten().collect()))
+            }
+            Collection::Mapping(mapping) => Ok(Value::Mapping(mapping)),
+        }
+    }
+}
+
+impl Node {
+    /// Wraps `node` with a branch for each of the `segments`, these branches
+    /// are created by `key`.
+    fn nested(segments: &[Segment], key: &str, node: Node) -> Node {
+        segments.iter().rev().fold(node, |node, segment| {
+            let mut branch = Branch::default();
+            branch.push(segment.clone(), key, node);
+            Node::Branch(branch)
+        })
+    }
+
+    fn is_empty_branch(&self) -> bool {
+        matches!(self, Node::Branch(branch) if branch.children.is_empty())
+    }
+
+    fn into_value(
+        self,
+        path: &mut Vec<Segment>,
+        options: &UnflattenOptions,
+        errors: &mut Errors,
+    ) -> Result<Value, Error> {
+        match self {
+            Node::Leaf(value) => Ok(value),
+            Node::Branch(branch) => branch.into_value(path, options, errors),
+        }
+    }
+}
+
+/// Infers the type of the mapping key `token`, returns `Some` if it is exactly
+/// how [`flatten()`] writes a boolean or number key.
+pub(crate) fn infer_key_type(token: &str) -> Option<Value> {
+    // Avoid invoking the YAML parser for most of the keys.
+    if !token
+        .starts_with(|char: char| char.is_ascii_digit() || matches!(char, '-' | '.' | 't' | 'f'))
+    {
+        return None;
+    }
+
+    match serde_yaml_ng::from_str(token) {
+        Ok(Value::Bool(boolean)) if boolean.to_string() == token => Some(Value::Bool(boolean)),
+        Ok(Value::Number(number)) if number.to_string() == token => Some(Value::Number(number)),
+        _ => None,
+    }
+}
+
+/// Resolves the plain scalar `scalar` with the YAML 1.2 core schema, returns
+/// the null, boolean or number it stands for, or `None` if it is a string.
+///
+/// Decimal integers that do not fit into 64 bits are resolved as floats,
+/// while only the octal and hexadecimal integers that do not fit into 64 bits
+/// are kept as strings.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::infer_scalar;
+/// assert_eq!(infer_scalar("~"), Some(Value::Null));
+/// assert_eq!(infer_scalar("TRUE"), Some(Value::Bool(true)));
+/// assert_eq!(infer_scalar("0x1F"), Some(Value::Number(31.into())));
+/// assert_eq!(infer_scalar("-.5e3"), Some(Value::Number((-500.0).into())));
+/// assert_eq!(infer_scalar("yes"), None);
+/// ```
+pub fn infer_scalar(scalar: &str) -> Option<Value> {
+    match scalar {
+        "" | "~" | "null" | "Null" | "NULL" => return Some(Value::Null),
+        "true" | "True" | "TRUE" => return Some(Value::Bool(true)),
+        "false" | "False" | "FALSE" => return Some(Value::Bool(false)),
+        ".nan" | ".NaN" | ".NAN" => return Some(Value::Number(f64::NAN.into())),
+        _ => {}
+    }
+    if let Some(octal) = scalar.strip_prefix("0o") {
+        return parse_radix(octal, 8);
+    }
+    if let Some(hexadecimal) = scalar.strip_prefix("0x") {
+        return parse_radix(hexadecimal, 16);
+    }
+
+    let unsigned = scalar.strip_prefix(['-', '+']).unwrap_or(scalar);
+    if matches!(unsigned, ".inf" | ".Inf" | ".INF") {
+        let infinity = match scalar.starts_with('-') {
+            true => f64::NEG_INFINITY,
+            false => f64::INFINITY,
+        };
+        return Some(Value::Number(infinity.into()));
+    }
+    if is_digits(unsigned) {
+        if let Ok(integer) = scalar.parse::<i64>() {
+            return Some(Value::Number(integer.into()));
+        }
+        if let Ok(integer) = scalar.parse::<u64>() {
+            return Some(Value::Number(integer.into()));
+        }
+    }
+    if !is_float(unsigned) {
+        return None;
+    }
+
+    scalar
+        .parse::<f64>()
+        .ok()
+        .map(|float| Value::Number(float.into()))
+}
+
+fn parse_radix(digits: &str, radix: u32) -> Option<Value> {
+    if digits.is_empty() || !digits.chars().all(|char| char.is_digit(radix)) {
+        return None;
     }
+
+    u64::from_str_radix(digits, radix)
+        .ok()
+        .map(|integer| Value::Number(integer.into()))
 }
 
-impl std::error::Error for Error {}
+fn is_digits(token: &str) -> bool {
+    !token.is_empty() && token.bytes().all(|byte| byte.is_ascii_digit())
+}
 
-/// Unflattens the given `input` YAML.
-///
-/// # Examples
-///
-/// ```rust
-/// # use serde_yaml_ng::from_str;
-/// # use serde_yaml_ng::Value;
-/// # use serde_yaml_nested::conversion::unflatten;
-/// let flattened: Value = from_str(r#"a.b.c: null"#).unwrap();
-/// let mapping = match flattened {
-///     Value::Mapping(mapping) => mapping,
-///     _ => unreachable!(),
-/// };
-///
-/// let nested = unflatten(
-///     mapping
-///         .into_iter()
-///         .map(|(key, value)| (key.as_str().unwrap().to_string(), value)),
-/// )
-/// .unwrap();
-///
-/// let expected: Value = from_str(
-///     r#"
-/// a:
-///   b:
-///     c: null"#,
-/// )
-/// .unwrap();
-/// assert_eq!(nested, expected);
-/// ```
-pub fn unflatten<I: IntoIterator<Item = (String, Value)>>(input: I) -> Result<Value, Error> {
-    let mut mapping = Mapping::new();
-    for (key, value) in input {
-        let mut split_by_dot = key.split(DOT).peekable();
-
-        let mut outermost_mapping = &mut mapping;
-        'inner: loop {
-            let token_str = split_by_dot
-                .next()
-                .expect("should be Some, guarded by last iteration");
-            let token = Value::String(token_str.into());
-
-            let key_is_last_key = split_by_dot.peek().is_none();
-
-            // We use `.get(&self)` to acquire if this key exists or not
-            // cannot use `.get_mut(&mut self)` as that will borrow
-            // `outermost_mapping` for more than once.
-            let exist = outermost_mapping.get(&token).is_some();
-
-            if exist {
-                let existing = outermost_mapping
-                    .get_mut(&token)
-                    .expect("should be Some as `exist` is true");
-                if key_is_last_key {
-                    return Err(Error::DuplicateValue {
-                        key: key.clone(),
-                        token: token_str.to_string(),
-                    });
-                } else if let Value::Mapping(new_mapping) = existing {
-                    outermost_mapping = new_mapping;
-                } else {
-                    return Err(Error::DuplicateValue {
-                        key: key.clone(),
-                        token: token_str.to_string(),
-                    });
-                }
-            } else if key_is_last_key {
-                outermost_mapping.insert(token, value);
-                break 'inner;
-            } else {
-                outermost_mapping.insert(token.clone(), Value::Mapping(Mapping::new()));
-                let newly_inserted_mapping = outermost_mapping
-                    .get_mut(&token)
-                    .unwrap()
-                    .as_mapping_mut()
-                    .unwrap();
-                outermost_mapping = newly_inserted_mapping;
-            }
+/// Checks `unsigned` against `(\.[0-9]+|[0-9]+(\.[0-9]*)?)([eE][-+]?[0-9]+)?`.
+fn is_float(unsigned: &str) -> bool {
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let valid_mantissa = match mantissa.split_once('.') {
+        Some(("", fraction)) => is_digits(fraction),
+        Some((integer, fraction)) => {
+            is_digits(integer) && (fraction.is_empty() || is_digits(fraction))
         }
+        None => is_digits(mantissa),
+    };
+    let valid_exponent = exponent.map_or(true, |exponent| {
+        is_digits(exponent.strip_prefix(['-', '+']).unwrap_or(exponent))
+    });
+
+    valid_mantissa && valid_exponent
+}
+
+/// Parses `token` as a sequence index, leading zeros are not allowed so that
+/// a token like `01` is still a mapping key.
+pub(crate) fn parse_index(token: &str) -> Option<usize> {
+    if token.len() > 1 && token.starts_with('0') {
+        return None;
+    }
+    if !token.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
     }
 
-    Ok(Value::Mapping(mapping))
+    token.parse().ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::path::Path;
     use pretty_assertions::assert_eq;
     use serde_yaml_ng::from_str;
     use serde_yaml_ng::Number;
@@ -198,7 +2121,7 @@ mod tests {
     #[test]
     fn test_flatten_one_layer() {
         let bool_null = "true: null";
-        let yaml = from_str::<Value>(&bool_null).unwrap();
+        let yaml = from_str::<Value>(bool_null).unwrap();
         let flattened = flatten(yaml);
         assert_eq!(
             flattened,
@@ -206,7 +2129,7 @@ mod tests {
         );
 
         let bool_bool = "true: true";
-        let yaml = from_str::<Value>(&bool_bool).unwrap();
+        let yaml = from_str::<Value>(bool_bool).unwrap();
         let flattened = flatten(yaml);
         assert_eq!(
             flattened,
@@ -214,7 +2137,7 @@ mod tests {
         );
 
         let bool_number = "true: 1";
-        let yaml = from_str::<Value>(&bool_number).unwrap();
+        let yaml = from_str::<Value>(bool_number).unwrap();
         let flattened = flatten(yaml);
         assert_eq!(
             flattened,
@@ -222,7 +2145,7 @@ mod tests {
         );
 
         let bool_str = "true: str";
-        let yaml = from_str::<Value>(&bool_str).unwrap();
+        let yaml = from_str::<Value>(bool_str).unwrap();
         let flattened = flatten(yaml);
         assert_eq!(
             flattened,
@@ -241,7 +2164,7 @@ str3: 1
 str4: hello
     "#;
 
-        let yaml = from_str::<Value>(&yaml_str).unwrap();
+        let yaml = from_str::<Value>(yaml_str).unwrap();
         let flattened = flatten(yaml);
 
         let expected = BTreeMap::from([
@@ -302,7 +2225,7 @@ str:
   str4: hello
 "#;
 
-        let yaml = from_str::<Value>(&yaml_str).unwrap();
+        let yaml = from_str::<Value>(yaml_str).unwrap();
 
         let flattened = flatten(yaml);
 
@@ -471,7 +2394,7 @@ str:
     str4: hello
 "#;
 
-        let yaml = from_str::<Value>(&yaml_str).unwrap();
+        let yaml = from_str::<Value>(yaml_str).unwrap();
         let flattened = flatten(yaml);
 
         let expected = BTreeMap::from([
@@ -592,7 +2515,7 @@ cluster.fault_detection:
     interval: 500
     retry: 9
 routing.allocation.same_shard.host: false"#;
-        let yaml: Value = from_str(&yaml_str).unwrap();
+        let yaml: Value = from_str(yaml_str).unwrap();
         let flattened = flatten(yaml);
         let expected = BTreeMap::from([
             (
@@ -633,7 +2556,7 @@ bootstrap.memory_lock: false
 cache.recycler.page.limit.heap: 10%
 cache.recycler.page.type: CONCURRENT
 cache.recycler.page.weight.bytes: 1.0"#;
-        let yaml: Value = from_str(&yaml_str).unwrap();
+        let yaml: Value = from_str(yaml_str).unwrap();
         let flattened = flatten(yaml);
 
         let expected = BTreeMap::from([
@@ -670,7 +2593,683 @@ cache.recycler.page.weight.bytes: 1.0"#;
             ),
         ]);
 
-        assert_eq!(flattened, expected);
+        assert_eq!(flattened, expected);
+    }
+
+    #[test]
+    fn test_flatten_sequences() {
+        let yaml_str = r#"
+discovery.seed_hosts:
+  - 192.168.1.10:9300
+  - 192.168.1.11
+cluster:
+  initial_master_nodes: [master-a, master-b]
+  routing:
+    - awareness: zone
+      attributes: [rack_id]
+    - []
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        let flattened = flatten(yaml.clone());
+        let expected = BTreeMap::from([
+            (
+                String::from("discovery.seed_hosts"),
+                from_str("[192.168.1.10:9300, 192.168.1.11]").unwrap(),
+            ),
+            (
+                String::from("cluster.initial_master_nodes"),
+                from_str("[master-a, master-b]").unwrap(),
+            ),
+            (
+                String::from("cluster.routing"),
+                from_str("[{awareness: zone, attributes: [rack_id]}, []]").unwrap(),
+            ),
+        ]);
+        assert_eq!(flattened, expected);
+
+        let flattened = flatten_with(yaml, &FlattenOptions::new().flatten_sequences(true));
+        let expected = BTreeMap::from([
+            (
+                String::from("discovery.seed_hosts.0"),
+                Value::String("192.168.1.10:9300".into()),
+            ),
+            (
+                String::from("discovery.seed_hosts.1"),
+                Value::String("192.168.1.11".into()),
+            ),
+            (
+                String::from("cluster.initial_master_nodes.0"),
+                Value::String("master-a".into()),
+            ),
+            (
+                String::from("cluster.initial_master_nodes.1"),
+                Value::String("master-b".into()),
+            ),
+            (
+                String::from("cluster.routing.0.awareness"),
+                Value::String("zone".into()),
+            ),
+            (
+                String::from("cluster.routing.0.attributes.0"),
+                Value::String("rack_id".into()),
+            ),
+            (
+                String::from("cluster.routing.1"),
+                Value::Sequence(Vec::new()),
+            ),
+        ]);
+        assert_eq!(flattened, expected);
+    }
+
+    #[test]
+    fn test_flatten_tagged() {
+        let yaml_str = r#"
+xpack.security.password: !secret foo
+include: !include x.yml
+nested:
+  tagged: !custom
+    a: [b, c]
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+        let flattened = flatten_with(yaml.clone(), &FlattenOptions::new().flatten_sequences(true));
+
+        let expected = BTreeMap::from([
+            (
+                String::from("xpack.security.password"),
+                from_str("!secret foo").unwrap(),
+            ),
+            (String::from("include"), from_str("!include x.yml").unwrap()),
+            (
+                String::from("nested.tagged"),
+                from_str("!custom {a: [b, c]}").unwrap(),
+            ),
+        ]);
+        assert_eq!(flattened, expected);
+        assert!(matches!(flattened["include"], Value::Tagged(_)));
+
+        let nested = unflatten(flattened).unwrap();
+        assert_eq!(nested, unflatten(flatten(yaml)).unwrap());
+        assert_eq!(nested["nested"]["tagged"], expected["nested.tagged"]);
+    }
+
+    #[test]
+    fn test_try_flatten_unsupported_keys() {
+        let yaml: Value = from_str("a: {b: {null: 1}}").unwrap();
+        let error = try_flatten(yaml).unwrap_err();
+        assert_eq!(
+            error,
+            Error::UnsupportedKey {
+                path: "a.b".into(),
+                key: Value::Null
+            }
+        );
+
+        let yaml: Value = from_str("[a, b]: 1").unwrap();
+        let error = try_flatten(yaml).unwrap_err();
+        assert_eq!(
+            error,
+            Error::UnsupportedKey {
+                path: "".into(),
+                key: from_str("[a, b]").unwrap()
+            }
+        );
+
+        let yaml: Value = from_str("a: [{{b: c}: 1}]").unwrap();
+        let error =
+            try_flatten_with(yaml, &FlattenOptions::new().flatten_sequences(true)).unwrap_err();
+        assert_eq!(
+            error,
+            Error::UnsupportedKey {
+                path: "a.0".into(),
+                key: from_str("{b: c}").unwrap()
+            }
+        );
+
+        let yaml: Value = from_str("a: {!secret b: 1}").unwrap();
+        let error = try_flatten(yaml).unwrap_err();
+        assert_eq!(
+            error,
+            Error::UnsupportedTag {
+                path: "a".into(),
+                tag: "!secret".into()
+            }
+        );
+
+        let yaml: Value = from_str("a: {b: 1, true: 2, 3: 4}").unwrap();
+        assert_eq!(try_flatten(yaml.clone()).unwrap(), flatten(yaml));
+    }
+
+    #[test]
+    #[should_panic(expected = "under path 'a', found a key 'Null' that cannot be flattened")]
+    fn test_flatten_unsupported_key_panics() {
+        let yaml: Value = from_str("a: {null: 1}").unwrap();
+        flatten(yaml);
+    }
+
+    #[test]
+    fn test_flatten_separator() {
+        let yaml_str = r#"
+cluster:
+  routing:
+    allocation.enable: all
+  hosts: [a, b]
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        for separator in ["/", "__", "::"] {
+            let flattened = flatten_with(
+                yaml.clone(),
+                &FlattenOptions::new()
+                    .separator(separator)
+                    .flatten_sequences(true),
+            );
+            let expected = BTreeMap::from([
+                (
+                    ["cluster", "routing", "allocation.enable"].join(separator),
+                    Value::String("all".into()),
+                ),
+                (
+                    ["cluster", "hosts", "0"].join(separator),
+                    Value::String("a".into()),
+                ),
+                (
+                    ["cluster", "hosts", "1"].join(separator),
+                    Value::String("b".into()),
+                ),
+            ]);
+            assert_eq!(flattened, expected);
+
+            let nested = unflatten_with(
+                flattened,
+                &UnflattenOptions::new().separator(separator).sequences(true),
+            )
+            .unwrap();
+            assert_eq!(nested, yaml);
+        }
+    }
+
+    #[test]
+    fn test_flatten_escaping() {
+        let yaml_str = r#"
+cluster:
+  node.attr: x
+  'back\slash': y
+  "0": z
+hosts: [a]
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        let flattened = flatten_with(yaml.clone(), &FlattenOptions::new().escaping(true));
+        let expected = BTreeMap::from([
+            (
+                String::from(r"cluster.node\.attr"),
+                Value::String("x".into()),
+            ),
+            (
+                String::from(r"cluster.back\\slash"),
+                Value::String("y".into()),
+            ),
+            (String::from(r"cluster.\0"), Value::String("z".into())),
+            (String::from("hosts"), from_str("[a]").unwrap()),
+        ]);
+        assert_eq!(flattened, expected);
+        let nested = unflatten_with(flattened, &UnflattenOptions::new().escaping(true)).unwrap();
+        assert_eq!(nested, yaml);
+
+        let flattened = flatten_with(
+            yaml.clone(),
+            &FlattenOptions::new()
+                .separator("__")
+                .escaping(true)
+                .flatten_sequences(true),
+        );
+        let expected = BTreeMap::from([
+            (
+                String::from("cluster__node.attr"),
+                Value::String("x".into()),
+            ),
+            (
+                String::from(r"cluster__back\\slash"),
+                Value::String("y".into()),
+            ),
+            (String::from(r"cluster__\0"), Value::String("z".into())),
+            (String::from("hosts__0"), Value::String("a".into())),
+        ]);
+        assert_eq!(flattened, expected);
+        let nested = unflatten_with(
+            flattened,
+            &UnflattenOptions::new()
+                .separator("__")
+                .escaping(true)
+                .sequences(true),
+        )
+        .unwrap();
+        assert_eq!(nested, yaml);
+    }
+
+    #[test]
+    #[should_panic(expected = "separator cannot be empty")]
+    fn test_empty_separator() {
+        FlattenOptions::new().separator("");
+    }
+
+    #[test]
+    fn test_flatten_ordered() {
+        let yaml_str = r#"
+path.data: /var/lib/data
+node:
+  name: node-1
+  roles: [master, data]
+cluster.name: es
+node.attr.zone: b
+discovery.seed_hosts:
+  - host-b
+  - host-a
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        let flattened = flatten_ordered(yaml.clone());
+        let keys: Vec<&str> = flattened.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "path.data",
+                "node.name",
+                "node.roles",
+                "cluster.name",
+                "node.attr.zone",
+                "discovery.seed_hosts"
+            ]
+        );
+
+        // The order is kept after a round trip
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let flattened = try_flatten_ordered_with(yaml, &options).unwrap();
+        let nested = unflatten_with(flattened, &UnflattenOptions::new().sequences(true)).unwrap();
+        let expected = r#"path:
+  data: /var/lib/data
+node:
+  name: node-1
+  roles:
+  - master
+  - data
+  attr:
+    zone: b
+cluster:
+  name: es
+discovery:
+  seed_hosts:
+  - host-b
+  - host-a
+"#;
+        assert_eq!(serde_yaml_ng::to_string(&nested).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_flatten_ordered_duplicate_paths() {
+        let yaml: Value = from_str("{a.b: 1, a: {b: 2}}").unwrap();
+
+        let flattened = flatten_ordered(yaml.clone());
+        assert_eq!(
+            flattened,
+            vec![
+                (String::from("a.b"), Value::Number(Number::from(1))),
+                (String::from("a.b"), Value::Number(Number::from(2))),
+            ]
+        );
+
+        let flattened = flatten(yaml);
+        assert_eq!(
+            flattened,
+            BTreeMap::from([(String::from("a.b"), Value::Number(Number::from(2)))])
+        );
+    }
+
+    #[test]
+    fn test_flatten_ref() {
+        let yaml: Value = from_str(
+            r#"
+a:
+  b: [x, y]
+  c: !secret foo
+d: {}
+e: []
+"#,
+        )
+        .unwrap();
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let flattened = try_flatten_ref_with(&yaml, &options).unwrap();
+        assert_eq!(
+            flattened,
+            BTreeMap::from([
+                (String::from("a.b.0"), &yaml["a"]["b"][0]),
+                (String::from("a.b.1"), &yaml["a"]["b"][1]),
+                (String::from("a.c"), &yaml["a"]["c"]),
+                (String::from("e"), &yaml["e"]),
+            ])
+        );
+
+        // Same as the owned version.
+        let owned: BTreeMap<String, Value> = flattened
+            .into_iter()
+            .map(|(path, value)| (path, value.clone()))
+            .collect();
+        assert_eq!(owned, flatten_with(yaml.clone(), &options));
+
+        let yaml: Value = from_str("a: {[b, c]: d}").unwrap();
+        assert_eq!(
+            try_flatten_ref_with(&yaml, &FlattenOptions::new()),
+            Err(Error::UnsupportedKey {
+                path: String::from("a"),
+                key: from_str("[b, c]").unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_flatten_iterator() {
+        let yaml: Value = from_str("{a: {b: 1, c: [x]}, d: 2}").unwrap();
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let mut flatten = Flatten::new(yaml.clone(), &options);
+        assert_eq!(
+            flatten.next(),
+            Some(Ok((String::from("a.b"), Value::Number(Number::from(1)))))
+        );
+        assert_eq!(
+            flatten.next(),
+            Some(Ok((String::from("a.c.0"), Value::String("x".into()))))
+        );
+        assert_eq!(
+            flatten.next(),
+            Some(Ok((String::from("d"), Value::Number(Number::from(2)))))
+        );
+        assert_eq!(flatten.next(), None);
+
+        let flattened: Vec<_> = FlattenRef::new(&yaml, &options)
+            .map(|entry| entry.map(|(path, value)| (path, value.clone())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(flattened, try_flatten_ordered_with(yaml, &options).unwrap());
+
+        // A root leaf has no path.
+        assert_eq!(Flatten::new(Value::Null, &options).next(), None);
+
+        // The iteration stops after an error.
+        let yaml: Value = from_str("{a: 1, b: {[c]: 2}, d: 3}").unwrap();
+        let mut flatten = Flatten::new(yaml, &options);
+        assert_eq!(
+            flatten.next(),
+            Some(Ok((String::from("a"), Value::Number(Number::from(1)))))
+        );
+        assert_eq!(
+            flatten.next(),
+            Some(Err(Error::UnsupportedKey {
+                path: String::from("b"),
+                key: from_str("[c]").unwrap(),
+            }))
+        );
+        assert_eq!(flatten.next(), None);
+    }
+
+    #[test]
+    fn test_flatten_deeply_nested() {
+        let depth = 10_000;
+        let mut yaml = Value::Null;
+        for _ in 0..depth {
+            let mut mapping = Mapping::new();
+            mapping.insert(Value::String("a".into()), yaml);
+            yaml = Value::Mapping(mapping);
+        }
+
+        let flattened: Vec<_> = FlattenRef::new(&yaml, &FlattenOptions::new()).collect();
+        assert_eq!(
+            flattened,
+            vec![Ok((vec!["a"; depth].join("."), &Value::Null))]
+        );
+
+        // Only dropping such a value is recursive, which needs a larger stack
+        // than the one of the test thread.
+        std::thread::Builder::new()
+            .stack_size(256 << 20)
+            .spawn(move || drop(yaml))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_flatten_max_depth() {
+        let yaml: Value = from_str("{a: {b: x}, c: {d: {e: y}}}").unwrap();
+
+        let options = FlattenOptions::new().max_depth(2);
+        let error = try_flatten_with(yaml.clone(), &options).unwrap_err();
+        assert_eq!(
+            error,
+            Error::DepthExceeded {
+                path: String::from("c.d.e"),
+                limit: 2,
+            }
+        );
+
+        let mut iterator = Flatten::new(yaml.clone(), &options);
+        assert!(matches!(iterator.next(), Some(Ok(_))));
+        assert!(matches!(
+            iterator.next(),
+            Some(Err(Error::DepthExceeded { .. }))
+        ));
+        assert_eq!(iterator.next(), None);
+
+        let options = FlattenOptions::new().max_depth(3);
+        assert_eq!(
+            try_flatten_with(yaml.clone(), &options).unwrap(),
+            flatten(yaml)
+        );
+    }
+
+    #[test]
+    fn test_flatten_paths() {
+        let yaml: Value = from_str("{a.b: {1: [x, y], c: []}, true: z}").unwrap();
+
+        let options = FlattenOptions::new().flatten_sequences(true).escaping(true);
+        let flattened = try_flatten_paths_with(yaml.clone(), &options).unwrap();
+        assert_eq!(
+            flattened,
+            vec![
+                (Path::parse(r"a\.b.\1.0"), Value::String("x".into())),
+                (Path::parse(r"a\.b.\1.1"), Value::String("y".into())),
+                (Path::parse(r"a\.b.c"), Value::Sequence(Vec::new())),
+                (Path::parse("true"), Value::String("z".into())),
+            ]
+        );
+
+        let options = UnflattenOptions::new().infer_key_types(true);
+        assert_eq!(unflatten_paths_with(flattened, &options).unwrap(), yaml);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let yaml: Value = from_str(
+            r#"
+cluster.name: es
+cluster:
+  routing:
+    allocation.enable: all
+  nodes: [x, y]
+node.attr: {box.type: hot}
+"#,
+        )
+        .unwrap();
+
+        let found = |key| lookup(&yaml, key).unwrap();
+        assert_eq!(found("cluster.name"), Some(&Value::String("es".into())));
+        assert_eq!(
+            found("cluster.routing.allocation.enable"),
+            Some(&Value::String("all".into()))
+        );
+        assert_eq!(found("cluster.routing"), Some(&yaml["cluster"]["routing"]));
+        assert_eq!(
+            found("node.attr.box.type"),
+            Some(&Value::String("hot".into()))
+        );
+        assert_eq!(found("cluster.nodes"), Some(&yaml["cluster"]["nodes"]));
+        assert_eq!(found("cluster.nodes.0"), None);
+        assert_eq!(found("cluster.routing.allocation"), None);
+        assert_eq!(found("node"), None);
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        assert_eq!(
+            lookup_with(&yaml, "cluster.nodes.1", &options),
+            Ok(Some(&Value::String("y".into())))
+        );
+
+        let options = FlattenOptions::new().escaping(true);
+        assert_eq!(lookup_with(&yaml, "node.attr.box.type", &options), Ok(None));
+        assert_eq!(
+            lookup_with(&yaml, r"node\.attr.box\.type", &options),
+            Ok(Some(&Value::String("hot".into())))
+        );
+    }
+
+    #[test]
+    fn test_lookup_ambiguous() {
+        let yaml: Value = from_str("{a: {b.c: 1, d: 2}, a.b: {c: 3}, a.d: 4}").unwrap();
+
+        assert_eq!(
+            lookup(&yaml, "a.b.c"),
+            Err(Error::DuplicateValue {
+                key: String::from(r"a\.b.c"),
+                token: String::from("c"),
+                prior: String::from(r"a.b\.c"),
+            })
+        );
+        assert_eq!(
+            lookup(&yaml, "a.d"),
+            Err(Error::DuplicateValue {
+                key: String::from(r"a\.d"),
+                token: String::from(r"a\.d"),
+                prior: String::from("a.d"),
+            })
+        );
+        assert_eq!(lookup(&yaml, "a"), Ok(Some(&yaml["a"])));
+    }
+
+    #[test]
+    fn test_normalize() {
+        let yaml_str = r#"
+cluster.fault_detection:
+  follower_check:
+    interval: 1000
+  master_check.interval: 500
+cluster:
+  name: es
+  fault_detection.follower_check.retry: 3
+routing.allocation.same_shard.host: false
+tags: [a, b]"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        let nested = normalize_nested(yaml.clone()).unwrap();
+        let expected: Value = from_str(
+            r#"
+cluster:
+  fault_detection:
+    follower_check:
+      interval: 1000
+      retry: 3
+    master_check:
+      interval: 500
+  name: es
+routing:
+  allocation:
+    same_shard:
+      host: false
+tags: [a, b]"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        let flat = normalize_flat(yaml).unwrap();
+        let expected: Value = from_str(
+            r#"
+cluster.fault_detection.follower_check.interval: 1000
+cluster.fault_detection.follower_check.retry: 3
+cluster.fault_detection.master_check.interval: 500
+cluster.name: es
+routing.allocation.same_shard.host: false
+tags: [a, b]"#,
+        )
+        .unwrap();
+        assert_eq!(flat, expected);
+        assert_eq!(normalize_flat(flat.clone()).unwrap(), flat);
+
+        assert_eq!(
+            normalize_nested(Value::Bool(true)).unwrap(),
+            Value::Bool(true)
+        );
+    }
+
+    #[test]
+    fn test_normalize_empty_mappings_and_key_types() {
+        let yaml: Value =
+            from_str("{a: {}, 1: x, b: 1, c: {d.e: {}}, 1.5: y, f: {}, f.g: z}").unwrap();
+
+        let nested = normalize_nested(yaml.clone()).unwrap();
+        let expected: Value =
+            from_str("{a: {}, 1: x, b: 1, c: {d: {e: {}}}, 1.5: y, f: {g: z}}").unwrap();
+        assert_eq!(nested, expected);
+        let keys: Vec<&Value> = nested.as_mapping().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            expected.as_mapping().unwrap().keys().collect::<Vec<_>>()
+        );
+
+        let flat = normalize_flat(yaml).unwrap();
+        let expected: Value = from_str("{a: {}, 1: x, b: 1, c.d.e: {}, 1.5: y, f.g: z}").unwrap();
+        assert_eq!(flat, expected);
+        assert_eq!(normalize_flat(flat.clone()).unwrap(), flat);
+
+        let yaml: Value = from_str(r#"{1: {a: x}, "1": {a: y}}"#).unwrap();
+        assert_eq!(
+            normalize_flat(yaml),
+            Err(Error::DuplicateValue {
+                key: String::from("1.a"),
+                token: String::from("a"),
+                prior: String::from("1.a"),
+            })
+        );
+
+        let yaml: Value = from_str("{a: {[k]: v}}").unwrap();
+        assert_eq!(
+            normalize_nested(yaml),
+            Err(Error::UnsupportedKey {
+                path: String::from("a"),
+                key: from_str("[k]").unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_normalize_conflicts() {
+        let yaml: Value = from_str("{a.b: 1, a: {b: 2}}").unwrap();
+        let error = Error::DuplicateValue {
+            key: String::from("a.b"),
+            token: String::from("b"),
+            prior: String::from("a.b"),
+        };
+        assert_eq!(normalize_nested(yaml.clone()), Err(error.clone()));
+        assert_eq!(normalize_flat(yaml), Err(error));
+
+        let yaml: Value = from_str("{a: {b: {c: 2}}, a.b: 1}").unwrap();
+        assert_eq!(
+            normalize_flat(yaml),
+            Err(Error::DuplicateValue {
+                key: String::from("a.b"),
+                token: String::from("b"),
+                prior: String::from("a.b.c"),
+            })
+        );
     }
 
     #[test]
@@ -758,7 +3357,8 @@ cache.recycler.page.weight.bytes: 1.0"#;
             error,
             Error::DuplicateValue {
                 key: "a".into(),
-                token: "a".into()
+                token: "a".into(),
+                prior: "a".into(),
             }
         );
 
@@ -771,7 +3371,8 @@ cache.recycler.page.weight.bytes: 1.0"#;
             error,
             Error::DuplicateValue {
                 key: "a.b.c".into(),
-                token: "b".into()
+                token: "b".into(),
+                prior: "a.b".into(),
             }
         );
 
@@ -784,7 +3385,781 @@ cache.recycler.page.weight.bytes: 1.0"#;
             error,
             Error::DuplicateValue {
                 key: "a.b".into(),
-                token: "b".into()
+                token: "b".into(),
+                prior: "a.b.c".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unflatten_into_mapping_value() {
+        let nested = unflatten([
+            ("a".into(), from_str("{b: 1}").unwrap()),
+            ("a.c.d".into(), Value::Number(Number::from(2))),
+        ])
+        .unwrap();
+        let expected: Value = from_str("a: {b: 1, c: {d: 2}}").unwrap();
+        assert_eq!(nested, expected);
+
+        let error = unflatten([
+            ("a".into(), from_str("{b: 1}").unwrap()),
+            ("a.b".into(), Value::Number(Number::from(2))),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a.b".into(),
+                token: "b".into(),
+                prior: "a".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unflatten_merge_mappings() {
+        let options = UnflattenOptions::new().merge_mappings(true);
+
+        let nested = unflatten_with(
+            [
+                ("a.c".into(), Value::Number(Number::from(2))),
+                ("a".into(), from_str("{b: 1}").unwrap()),
+                (
+                    "cluster".into(),
+                    from_str("{routing: {allocation.enable: all}}").unwrap(),
+                ),
+                (
+                    "cluster.routing.allocation".into(),
+                    from_str("{same_shard.host: false}").unwrap(),
+                ),
+                ("cluster.name".into(), Value::String("es".into())),
+                ("empty".into(), from_str("{}").unwrap()),
+                ("tagged".into(), from_str("!custom {a.b: c}").unwrap()),
+            ],
+            &options,
+        )
+        .unwrap();
+        let expected: Value = from_str(
+            r#"
+a: {c: 2, b: 1}
+cluster:
+  routing:
+    allocation:
+      enable: all
+      same_shard:
+        host: false
+  name: es
+empty: {}
+tagged: !custom {a.b: c}
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        // The result is the same as unflattening the flattened input
+        let yaml_str = r#"
+cluster.fault_detection:
+  follower_check:
+    interval: 1000
+  master_check.retry: 9
+cluster.fault_detection.follower_check.retry: 3
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+        let Value::Mapping(mapping) = yaml.clone() else {
+            unreachable!()
+        };
+        let nested = unflatten_with(
+            mapping
+                .into_iter()
+                .map(|(key, value)| (key.as_str().unwrap().to_string(), value)),
+            &options,
+        )
+        .unwrap();
+        assert_eq!(nested, unflatten(flatten(yaml)).unwrap());
+    }
+
+    #[test]
+    fn test_unflatten_merge_mappings_conflicts() {
+        let options = UnflattenOptions::new().merge_mappings(true);
+
+        let error = unflatten_with(
+            [
+                ("a".into(), from_str("{b: 1}").unwrap()),
+                ("a.b".into(), Value::Number(Number::from(2))),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a.b".into(),
+                token: "b".into(),
+                prior: "a.b".into(),
+            }
+        );
+
+        let error = unflatten_with(
+            [
+                ("a.b".into(), Value::Number(Number::from(2))),
+                ("a".into(), from_str("{b.c: 1}").unwrap()),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a.b.c".into(),
+                token: "b".into(),
+                prior: "a.b".into(),
+            }
+        );
+
+        let error = unflatten_with(
+            [
+                ("a".into(), Value::Null),
+                ("a".into(), from_str("{}").unwrap()),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a".into(),
+                token: "a".into(),
+                prior: "a".into(),
+            }
+        );
+
+        let error =
+            unflatten_with([("a".into(), from_str("{b: {~: 1}}").unwrap())], &options).unwrap_err();
+        assert_eq!(
+            error,
+            Error::UnsupportedKey {
+                path: "a.b".into(),
+                key: Value::Null
+            }
+        );
+    }
+
+    #[test]
+    fn test_unflatten_conflict_policies() {
+        let input: Vec<(String, Value)> = vec![
+            ("a.b".into(), Value::Number(Number::from(1))),
+            ("a.b".into(), Value::Number(Number::from(2))),
+            ("a.b.c".into(), Value::Number(Number::from(3))),
+            ("x.y.z".into(), Value::Number(Number::from(4))),
+            ("x.y".into(), Value::Number(Number::from(5))),
+            ("m".into(), from_str("{k: v}").unwrap()),
+            ("m.k.l".into(), Value::String("w".into())),
+        ];
+
+        let error = unflatten_with(input.clone(), &UnflattenOptions::new()).unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a.b".into(),
+                token: "b".into(),
+                prior: "a.b".into(),
+            }
+        );
+
+        let options = UnflattenOptions::new().on_conflict(ConflictPolicy::FirstWins);
+        let nested = unflatten_with(input.clone(), &options).unwrap();
+        let expected: Value = from_str("{a: {b: 1}, x: {y: {z: 4}}, m: {k: v}}").unwrap();
+        assert_eq!(nested, expected);
+
+        let options = UnflattenOptions::new().on_conflict(ConflictPolicy::LastWins);
+        let nested = unflatten_with(input.clone(), &options).unwrap();
+        let expected: Value = from_str("{a: {b: {c: 3}}, x: {y: 5}, m: {k: {l: w}}}").unwrap();
+        assert_eq!(nested, expected);
+
+        let options = UnflattenOptions::new().on_conflict(ConflictPolicy::CollectAll);
+        let error = unflatten_with(input, &options).unwrap_err();
+        let duplicate = |key: &str, token: &str, prior: &str| Error::DuplicateValue {
+            key: key.into(),
+            token: token.into(),
+            prior: prior.into(),
+        };
+        assert_eq!(
+            error,
+            Error::Conflicts(vec![
+                duplicate("a.b", "b", "a.b"),
+                duplicate("a.b.c", "b", "a.b"),
+                duplicate("x.y", "y", "x.y.z"),
+                duplicate("m.k.l", "k", "m"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unflatten_last_wins_replacement_errors() {
+        let input: Vec<(String, Value)> = vec![
+            ("a".into(), from_str("{b: 1}").unwrap()),
+            ("a.b.1".into(), Value::String("x".into())),
+        ];
+        let options = UnflattenOptions::new()
+            .sequences(true)
+            .on_conflict(ConflictPolicy::LastWins);
+
+        assert_eq!(
+            unflatten_with(input, &options),
+            Err(Error::InvalidSequence {
+                path: "a.b".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 0 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_unflatten_collect_all() {
+        let input: Vec<(String, Value)> = vec![
+            ("a.b".into(), Value::Null),
+            ("a.b.c".into(), Value::Null),
+            ("s.0".into(), Value::Null),
+            ("s.2".into(), Value::Null),
+            ("m.0.x".into(), Value::Null),
+            ("m.y.1".into(), Value::Null),
+            ("n".into(), from_str("{[k]: v}").unwrap()),
+            ("a.b".into(), Value::Null),
+        ];
+        let options = UnflattenOptions::new()
+            .sequences(true)
+            .merge_mappings(true)
+            .on_conflict(ConflictPolicy::CollectAll);
+
+        let error = unflatten_with(input.clone(), &options).unwrap_err();
+        let errors = vec![
+            Error::DuplicateValue {
+                key: "a.b.c".into(),
+                token: "b".into(),
+                prior: "a.b".into(),
+            },
+            Error::UnsupportedKey {
+                path: "n".into(),
+                key: from_str("[k]").unwrap(),
+            },
+            Error::DuplicateValue {
+                key: "a.b".into(),
+                token: "b".into(),
+                prior: "a.b".into(),
+            },
+            Error::InvalidSequence {
+                path: "s".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 1 },
+            },
+            Error::InvalidSequence {
+                path: "m".into(),
+                kind: SequenceErrorKind::MixedTokens {
+                    index: "0".into(),
+                    name: "y".into(),
+                },
+            },
+            Error::InvalidSequence {
+                path: "m.y".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 0 },
+            },
+        ];
+        assert_eq!(error, Error::Conflicts(errors.clone()));
+
+        // Only the first one is returned by the other policies
+        let error = unflatten_with(input, &options.on_conflict(ConflictPolicy::Error));
+        assert_eq!(error, Err(errors[0].clone()));
+    }
+
+    #[test]
+    fn test_unflatten_layered_last_wins() {
+        let defaults: Value = from_str(
+            r#"
+cluster:
+  name: default
+  routing.allocation.enable: all
+path.data: /var/lib/data
+"#,
+        )
+        .unwrap();
+        let node: Value = from_str(
+            r#"
+cluster.name: node
+path:
+  data: [/mnt/a, /mnt/b]
+  logs: /var/log
+"#,
+        )
+        .unwrap();
+
+        let layers = [defaults, node].into_iter().flat_map(|layer| {
+            let Value::Mapping(mapping) = layer else {
+                unreachable!()
+            };
+            mapping
+                .into_iter()
+                .map(|(key, value)| (key.as_str().unwrap().to_string(), value))
+        });
+        let options = UnflattenOptions::new()
+            .merge_mappings(true)
+            .on_conflict(ConflictPolicy::LastWins);
+        let nested = unflatten_with(layers, &options).unwrap();
+
+        let expected: Value = from_str(
+            r#"
+cluster:
+  name: node
+  routing:
+    allocation:
+      enable: all
+path:
+  data: [/mnt/a, /mnt/b]
+  logs: /var/log
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+    }
+
+    #[test]
+    fn test_unflatten_sequences() {
+        let flattened = [
+            ("discovery.seed_hosts.1".into(), Value::String("b".into())),
+            ("discovery.seed_hosts.0".into(), Value::String("a".into())),
+            (
+                "cluster.routing.0.awareness".into(),
+                Value::String("zone".into()),
+            ),
+            (
+                "cluster.routing.0.attributes.0".into(),
+                Value::String("rack_id".into()),
+            ),
+            ("cluster.routing.1".into(), Value::Sequence(Vec::new())),
+            ("cluster.name.01".into(), Value::Null),
+            ("cluster.mapping".into(), from_str("{0: x}").unwrap()),
+        ];
+
+        let nested =
+            unflatten_with(flattened.clone(), &UnflattenOptions::new().sequences(true)).unwrap();
+        let expected: Value = from_str(
+            r#"
+discovery:
+  seed_hosts: [a, b]
+cluster:
+  routing:
+    - awareness: zone
+      attributes: [rack_id]
+    - []
+  name:
+    "01": null
+  mapping: {0: x}
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        // Tokens are kept as mapping keys by default
+        let nested = unflatten(flattened).unwrap();
+        let seed_hosts = &nested["discovery"]["seed_hosts"];
+        assert_eq!(seed_hosts["0"], Value::String("a".into()));
+        assert_eq!(seed_hosts["1"], Value::String("b".into()));
+    }
+
+    #[test]
+    fn test_unflatten_sequences_round_trip() {
+        let yaml_str = r#"
+a: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]
+b:
+  - c: [x, [y, z]]
+  - {}
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+        let flattened = flatten_with(yaml, &FlattenOptions::new().flatten_sequences(true));
+        let nested = unflatten_with(flattened, &UnflattenOptions::new().sequences(true)).unwrap();
+
+        // Empty mappings are lost during flatten
+        let expected: Value = from_str(
+            r#"
+a: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]
+b:
+  - c: [x, [y, z]]
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+    }
+
+    #[test]
+    fn test_unflatten_separator() {
+        let options = UnflattenOptions::new().separator("__");
+
+        let nested = unflatten_with(
+            [
+                ("ES__CLUSTER__NAME".into(), Value::String("es".into())),
+                ("ES__NODE.NAME".into(), Value::String("node".into())),
+            ],
+            &options,
+        )
+        .unwrap();
+        let expected: Value = from_str(
+            r#"
+ES:
+  CLUSTER:
+    NAME: es
+  NODE.NAME: node
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        let error = unflatten_with(
+            [
+                ("a__b".into(), Value::Null),
+                ("a__b__c".into(), Value::Null),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: "a__b__c".into(),
+                token: "b".into(),
+                prior: "a__b".into(),
+            }
+        );
+
+        let error = unflatten_with(
+            [("a::1".into(), Value::Null)],
+            &UnflattenOptions::new().separator("::").sequences(true),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::InvalidSequence {
+                path: "a".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 0 },
+            }
+        );
+    }
+
+    #[test]
+    fn test_unflatten_escaping() {
+        let options = UnflattenOptions::new().escaping(true).sequences(true);
+
+        let nested = unflatten_with(
+            [
+                (r"a\.b.c".into(), Value::Null),
+                (r"d\\.e".into(), Value::Null),
+                (r"f.\0".into(), Value::Null),
+                (r"g.\x\".into(), Value::Null),
+            ],
+            &options,
+        )
+        .unwrap();
+        let expected: Value = from_str(
+            r#"
+a.b:
+  c: null
+'d\':
+  e: null
+f:
+  "0": null
+g:
+  x: null
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        let error = unflatten_with(
+            [
+                (r"a\.b.0".into(), Value::Null),
+                (r"a\.b.\1".into(), Value::Null),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::InvalidSequence {
+                path: r"a\.b".into(),
+                kind: SequenceErrorKind::MixedTokens {
+                    index: "0".into(),
+                    name: "1".into()
+                },
+            }
+        );
+
+        // Backslashes are normal characters by default
+        let nested = unflatten([(r"a\.b".into(), Value::Null)]).unwrap();
+        let expected: Value = from_str(r"'a\': {b: null}").unwrap();
+        assert_eq!(nested, expected);
+    }
+
+    #[test]
+    fn test_unflatten_infer_key_types() {
+        let yaml_str = r#"
+1:
+  true: a
+  false: b
+  -2: c
+  "3": d
+1.5: e
+.inf: f
+".inf": g
+"-1": h
+str: i
+"#;
+        let yaml: Value = from_str(yaml_str).unwrap();
+
+        let options = UnflattenOptions::new().infer_key_types(true);
+        let flattened = flatten_with(yaml.clone(), &FlattenOptions::new().escaping(true));
+        assert_eq!(flattened[r"\.inf"], Value::String("f".into()));
+        assert_eq!(flattened[r"\.\inf"], Value::String("g".into()));
+        assert_eq!(flattened[r"\-1"], Value::String("h".into()));
+        let nested = unflatten_with(flattened, &options.clone().escaping(true)).unwrap();
+        assert_eq!(nested, yaml);
+
+        // Without escaping, string keys that look like numbers are inferred
+        // as numbers, `1.5` and `.inf` are split, and `.inf` is overwritten
+        let nested = unflatten_with(flatten(yaml), &options).unwrap();
+        let expected: Value = from_str(
+            r#"
+1:
+  true: a
+  false: b
+  -2: c
+  3: d
+  5: e
+"":
+  inf: g
+-1: h
+str: i
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        // Only the way `flatten` writes keys is inferred
+        let nested = unflatten_with(
+            [
+                ("True".into(), Value::Null),
+                ("0x10".into(), Value::Null),
+                ("01".into(), Value::Null),
+                ("+1".into(), Value::Null),
+            ],
+            &options,
+        )
+        .unwrap();
+        let expected: Value =
+            from_str(r#"{"True": null, "0x10": null, "01": null, "+1": null}"#).unwrap();
+        assert_eq!(nested, expected);
+
+        let nested = unflatten_with(
+            [("a.0".into(), Value::Null), ("a.1".into(), Value::Null)],
+            &options.sequences(true),
+        )
+        .unwrap();
+        let expected: Value = from_str("a: [null, null]").unwrap();
+        assert_eq!(nested, expected);
+    }
+
+    #[test]
+    fn test_infer_scalar() {
+        let cases = [
+            ("", Some(Value::Null)),
+            ("Null", Some(Value::Null)),
+            ("False", Some(Value::Bool(false))),
+            ("-0", Some(Value::Number(0.into()))),
+            ("+12", Some(Value::Number(12.into()))),
+            ("007", Some(Value::Number(7.into()))),
+            ("0o17", Some(Value::Number(15.into()))),
+            ("18446744073709551615", Some(Value::Number(u64::MAX.into()))),
+            (
+                "18446744073709551616",
+                Some(Value::Number(18446744073709551616.0.into())),
+            ),
+            ("1.", Some(Value::Number(1.0.into()))),
+            ("2E-1", Some(Value::Number(0.2.into()))),
+            ("-.Inf", Some(Value::Number(f64::NEG_INFINITY.into()))),
+            ("yes", None),
+            ("tRue", None),
+            ("-0x10", None),
+            ("0o8", None),
+            ("0x", None),
+            (".", None),
+            ("1e", None),
+            ("1_000", None),
+            ("inf", None),
+            ("-.nan", None),
+            (" 1", None),
+        ];
+        for (scalar, expected) in cases {
+            assert_eq!(infer_scalar(scalar), expected, "{:?}", scalar);
+        }
+        assert!(infer_scalar(".NaN").unwrap().as_f64().unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_unflatten_infer_value_types() {
+        let input = [
+            ("a.b".into(), Value::String("128".into())),
+            ("a.c".into(), Value::String("true".into())),
+            ("a.d".into(), Value::String("~".into())),
+            ("a.e".into(), Value::String("1.5e3".into())),
+            ("a.f".into(), Value::String("on".into())),
+            ("a.g".into(), from_str(r#"["1", "2"]"#).unwrap()),
+            ("a.h".into(), from_str(r#"!tag "1""#).unwrap()),
+        ];
+
+        let options = UnflattenOptions::new().infer_value_types(true);
+        let nested = unflatten_with(input.clone(), &options).unwrap();
+        let expected: Value = from_str(
+            r#"
+a:
+  b: 128
+  c: true
+  d: null
+  e: 1500.0
+  f: "on"
+  g: ["1", "2"]
+  h: !tag "1"
+"#,
+        )
+        .unwrap();
+        assert_eq!(nested, expected);
+
+        let nested = unflatten(input).unwrap();
+        assert_eq!(nested["a"]["b"], Value::String("128".into()));
+    }
+
+    #[test]
+    fn test_unflatten_invalid_sequences() {
+        let options = UnflattenOptions::new().sequences(true);
+
+        let error = unflatten_with(
+            [("a.b.0".into(), Value::Null), ("a.b.2".into(), Value::Null)],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::InvalidSequence {
+                path: "a.b".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 1 },
+            }
+        );
+
+        let error = unflatten_with([("a.1".into(), Value::Null)], &options).unwrap_err();
+        assert_eq!(
+            error,
+            Error::InvalidSequence {
+                path: "a".into(),
+                kind: SequenceErrorKind::MissingIndex { index: 0 },
+            }
+        );
+
+        let error = unflatten_with(
+            [
+                ("a.b.1".into(), Value::Null),
+                ("a.b.c".into(), Value::Null),
+                ("a.b.0".into(), Value::Null),
+            ],
+            &options,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::InvalidSequence {
+                path: "a.b".into(),
+                kind: SequenceErrorKind::MixedTokens {
+                    index: "0".into(),
+                    name: "c".into()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_unflatten_max_depth() {
+        let input = || {
+            [
+                (String::from("a.b"), Value::Null),
+                (String::from("c.d.e"), Value::Null),
+                (String::from("f"), from_str("{g.h: null, i: null}").unwrap()),
+            ]
+        };
+        let error = Error::DepthExceeded {
+            path: String::from("c.d.e"),
+            limit: 2,
+        };
+
+        let options = UnflattenOptions::new().max_depth(2);
+        assert_eq!(unflatten_with(input(), &options), Err(error.clone()));
+
+        // Mapping values are not counted unless they are merged.
+        let options = UnflattenOptions::new().max_depth(3);
+        assert!(unflatten_with(input(), &options).is_ok());
+
+        let options = UnflattenOptions::new()
+            .max_depth(2)
+            .merge_mappings(true)
+            .on_conflict(ConflictPolicy::CollectAll);
+        assert_eq!(
+            unflatten_with(input(), &options),
+            Err(Error::Conflicts(vec![
+                error,
+                Error::DepthExceeded {
+                    path: String::from("f.g.h"),
+                    limit: 2,
+                },
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_unflatten_deeply_nested() {
+        let depth = 10_000;
+        let key = vec!["0"; depth].join(".");
+
+        let options = UnflattenOptions::new().sequences(true);
+        let mut nested = unflatten_with([(key, Value::Null)], &options).unwrap();
+        for _ in 0..depth {
+            nested = match nested {
+                Value::Sequence(mut sequence) if sequence.len() == 1 => sequence.pop().unwrap(),
+                unexpected => panic!("unexpected value: {:?}", unexpected),
+            };
+        }
+        assert_eq!(nested, Value::Null);
+    }
+
+    #[test]
+    fn test_unflatten_paths() {
+        let nested = unflatten_paths_with(
+            [
+                (Path::parse("a.1"), Value::String("y".into())),
+                (Path::parse("a.0"), Value::String("x".into())),
+                (Path::parse(r"b.\0"), Value::Null),
+            ],
+            &UnflattenOptions::new(),
+        )
+        .unwrap();
+        let expected: Value = from_str("{a: [x, y], b: {'0': null}}").unwrap();
+        assert_eq!(nested, expected);
+
+        let error = unflatten_paths_with(
+            [
+                (Path::parse("a.b"), Value::Null),
+                (Path::parse("a.b.c"), Value::Null),
+            ],
+            &UnflattenOptions::new().separator("/"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            Error::DuplicateValue {
+                key: String::from("a/b/c"),
+                token: String::from("b"),
+                prior: String::from("a/b"),
             }
         );
     }
@@ -0,0 +1,320 @@
+//! This module provides [`diff()`] to compare two YAML documents leaf by
+//! leaf, using the [`Path`]s of the leaves that
+//! [`flatten()`](crate::conversion::flatten) walks through. The changes can
+//! be turned into a [`Patch`](crate::patch::Patch).
+
+use crate::conversion::try_flatten_ref_all_paths_with;
+use crate::conversion::Error;
+use crate::conversion::FlattenOptions;
+use crate::path::find_key;
+use crate::path::get_path;
+use crate::path::Path;
+use crate::path::PathSegment;
+use serde_yaml_ng::Value;
+use std::collections::BTreeMap;
+
+/// A difference between two YAML documents at `path`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// `path` only exists in the new document.
+    Added { path: Path, value: Value },
+    /// `path` only exists in the old document.
+    Removed { path: Path, value: Value },
+    /// `path` exists in both documents with different values.
+    Modified { path: Path, old: Value, new: Value },
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Modified { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+/// Compares the `old` and `new` YAML documents, returns the changes ordered
+/// by their paths, so that indexes are ordered as numbers.
+///
+/// This is equivalent to calling [`try_diff_with()`] with the default
+/// [`FlattenOptions`], so sequences are compared as a whole.
+///
+/// Each mapping key is a single segment, so a dotted key and the nested
+/// mappings it stands for are different, use
+/// [`normalize_nested()`](crate::conversion::normalize_nested) on both
+/// documents to compare them regardless of how they are written.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::diff::diff;
+/// # use serde_yaml_nested::diff::Change;
+/// # use serde_yaml_nested::path::Path;
+/// let old: Value = from_str("cluster: {name: es, routing: {enable: all}}").unwrap();
+/// let new: Value = from_str("cluster: {name: prod, nodes: 3}").unwrap();
+///
+/// assert_eq!(
+///     diff(&old, &new),
+///     vec![
+///         Change::Modified {
+///             path: Path::from("cluster.name"),
+///             old: Value::String("es".into()),
+///             new: Value::String("prod".into()),
+///         },
+///         Change::Added {
+///             path: Path::from("cluster.nodes"),
+///             value: Value::Number(3.into()),
+///         },
+///         Change::Removed {
+///             path: Path::from("cluster.routing"),
+///             value: from_str("enable: all").unwrap(),
+///         },
+///     ]
+/// );
+/// ```
+///
+/// # Panics
+///
+/// Panics if either document contains a mapping key that cannot be
+/// flattened, use [`try_diff_with()`] to handle that as an error.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    try_diff_with(old, new, &FlattenOptions::default()).unwrap_or_else(|error| panic!("{}", error))
+}
+
+/// Compares the `old` and `new` YAML documents flattened according to
+/// `options`, returns the changes ordered by their paths.
+///
+/// With [`FlattenOptions::flatten_sequences()`] enabled, sequences are
+/// compared element by element, otherwise a sequence is a single leaf.
+/// Tagged values are always compared as a whole. Empty mappings are leaves
+/// too, and so is the root when it is not flattened, with the empty path.
+///
+/// A key or an element that only exists in one document is added or removed
+/// as a whole, and so is a value that changes its kind, e.g., a scalar that
+/// becomes a mapping, so that the [`Patch`](crate::patch::Patch) built from
+/// the changes turns `old` into `new`.
+pub fn try_diff_with(
+    old: &Value,
+    new: &Value,
+    options: &FlattenOptions,
+) -> Result<Vec<Change>, Error> {
+    let old_leaves: BTreeMap<Path, &Value> = try_flatten_ref_all_paths_with(old, options)?
+        .into_iter()
+        .collect();
+    let new_leaves: BTreeMap<Path, &Value> = try_flatten_ref_all_paths_with(new, options)?
+        .into_iter()
+        .collect();
+
+    let mut changes = BTreeMap::new();
+    for (path, old_leaf) in &old_leaves {
+        let change = match new_leaves.get(path) {
+            Some(new_leaf) if new_leaf == old_leaf => continue,
+            Some(new_leaf) => Change::Modified {
+                path: path.clone(),
+                old: (*old_leaf).clone(),
+                new: (*new_leaf).clone(),
+            },
+            None => match divergence(path, new) {
+                (path, true) => Change::Removed {
+                    value: lookup(old, &path),
+                    path,
+                },
+                (path, false) => Change::Modified {
+                    old: lookup(old, &path),
+                    new: lookup(new, &path),
+                    path,
+                },
+            },
+        };
+        changes.insert(change.path().clone(), change);
+    }
+    for path in new_leaves.keys() {
+        if old_leaves.contains_key(path) {
+            continue;
+        }
+
+        let change = match divergence(path, old) {
+            (path, true) => Change::Added {
+                value: lookup(new, &path),
+                path,
+            },
+            (path, false) => Change::Modified {
+                old: lookup(old, &path),
+                new: lookup(new, &path),
+                path,
+            },
+        };
+        changes.insert(change.path().clone(), change);
+    }
+
+    // A modified value covers the changes under it, e.g., an empty sequence
+    // that is a leaf of `old` and has elements in `new`. These changes come
+    // right after it.
+    let mut ordered: Vec<Change> = Vec::new();
+    for change in changes.into_values() {
+        if ordered
+            .last()
+            .is_some_and(|last| change.path().starts_with(last.path()))
+        {
+            continue;
+        }
+        ordered.push(change);
+    }
+
+    Ok(ordered)
+}
+
+/// Walks `other` along `path`, the path of a leaf of the other document, and
+/// returns where they diverge, with `true` if `other` has no value there, or
+/// `false` if its value there is of another kind.
+fn divergence(path: &Path, other: &Value) -> (Path, bool) {
+    let mut current = other;
+    for (nth, segment) in path.iter().enumerate() {
+        let child = match (current, segment) {
+            (Value::Mapping(mapping), PathSegment::Key(_)) => {
+                find_key(mapping, segment).and_then(|key| mapping.get(key))
+            }
+            (Value::Sequence(sequence), PathSegment::Index(index)) => sequence.get(*index),
+            _ => return (path.segments()[..nth].iter().cloned().collect(), false),
+        };
+        match child {
+            Some(child) => current = child,
+            None => return (path.segments()[..=nth].iter().cloned().collect(), true),
+        }
+    }
+
+    (path.clone(), false)
+}
+
+/// Returns a copy of the value at `path`, which is known to exist.
+fn lookup(value: &Value, path: &Path) -> Value {
+    get_path(value, path)
+        .expect("the path leads to a leaf or to one of its parents")
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversion::normalize_nested;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_diff_sequences() {
+        let old: Value = from_str("{a: [x, y], b: !tag {c: 1}, d: []}").unwrap();
+        let new: Value = from_str("{a: [x, z, w], b: !tag {c: 2}, d: []}").unwrap();
+
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Modified {
+                    path: Path::from("a"),
+                    old: from_str("[x, y]").unwrap(),
+                    new: from_str("[x, z, w]").unwrap(),
+                },
+                Change::Modified {
+                    path: Path::from("b"),
+                    old: from_str("!tag {c: 1}").unwrap(),
+                    new: from_str("!tag {c: 2}").unwrap(),
+                },
+            ]
+        );
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        assert_eq!(
+            try_diff_with(&old, &new, &options).unwrap(),
+            vec![
+                Change::Modified {
+                    path: Path::from("a.1"),
+                    old: Value::String("y".into()),
+                    new: Value::String("z".into()),
+                },
+                Change::Added {
+                    path: Path::from("a.2"),
+                    value: Value::String("w".into()),
+                },
+                Change::Modified {
+                    path: Path::from("b"),
+                    old: from_str("!tag {c: 1}").unwrap(),
+                    new: from_str("!tag {c: 2}").unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_order_and_dotted_keys() {
+        let old: Value = from_str("{a: [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]}").unwrap();
+        let new: Value = from_str("{a: [0, 1, x, 3, 4, 5, 6, 7, 8, 9, y]}").unwrap();
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let paths: Vec<Path> = try_diff_with(&old, &new, &options)
+            .unwrap()
+            .iter()
+            .map(|change| change.path().clone())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![Path::from("a.2"), Path::from("a.10"), Path::from("a.11")]
+        );
+
+        let old: Value = from_str("{a.b: 1}").unwrap();
+        let new: Value = from_str("{a.b: 2, a: {b: 1}}").unwrap();
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                Change::Added {
+                    path: Path::from("a"),
+                    value: from_str("{b: 1}").unwrap(),
+                },
+                Change::Modified {
+                    path: Path::from(r"a\.b"),
+                    old: Value::Number(1.into()),
+                    new: Value::Number(2.into()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_empty_mappings_and_root() {
+        let old: Value = from_str("{a: {}, b: 1}").unwrap();
+        let new: Value = from_str("{b: 1}").unwrap();
+        let removed = Change::Removed {
+            path: Path::from("a"),
+            value: Value::Mapping(Default::default()),
+        };
+        assert_eq!(diff(&old, &new), vec![removed]);
+        assert_eq!(diff(&old, &old), Vec::new());
+
+        assert_eq!(
+            diff(&Value::String("x".into()), &Value::String("y".into())),
+            vec![Change::Modified {
+                path: Path::new(),
+                old: Value::String("x".into()),
+                new: Value::String("y".into()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_diff_identical_and_errors() {
+        let yaml: Value = from_str("{a.b: 1, a: {c: [x]}}").unwrap();
+        let nested: Value = from_str("a: {b: 1, c: [x]}").unwrap();
+        assert_eq!(diff(&yaml, &yaml), Vec::new());
+        assert_eq!(
+            diff(&normalize_nested(yaml.clone()).unwrap(), &nested),
+            Vec::new()
+        );
+
+        let invalid: Value = from_str("{[a]: 1}").unwrap();
+        assert!(matches!(
+            try_diff_with(&yaml, &invalid, &FlattenOptions::new()),
+            Err(Error::UnsupportedKey { .. })
+        ));
+    }
+}
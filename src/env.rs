@@ -0,0 +1,362 @@
+//! This module provides [`overlay_env()`] to override the settings of a YAML
+//! document with environment variables, e.g.,
+//! `ES_CLUSTER__ROUTING__ALLOCATION__ENABLE=none` sets
+//! `cluster.routing.allocation.enable`.
+
+use crate::conversion::infer_scalar;
+use crate::conversion::normalize_nested;
+use crate::conversion::ConflictPolicy;
+use crate::conversion::Error;
+use crate::conversion::UnflattenOptions;
+use crate::path::find_key;
+use crate::path::get_path;
+use crate::path::set_path;
+use crate::path::Path;
+use serde_yaml_ng::Value;
+
+/// Options to customize how environment variables are turned into dotted
+/// keys by [`parse_env()`] and [`overlay_env()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvOptions {
+    prefix: String,
+    separator: String,
+    lowercase: bool,
+    infer_types: bool,
+    on_conflict: ConflictPolicy,
+}
+
+impl Default for EnvOptions {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            separator: String::from("__"),
+            lowercase: true,
+            infer_types: false,
+            on_conflict: ConflictPolicy::LastWins,
+        }
+    }
+}
+
+impl EnvOptions {
+    /// Creates the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the prefix of the variables to use, e.g., `ES_`, it is removed
+    /// from the keys. Variables without the prefix are ignored, the match is
+    /// case-sensitive.
+    ///
+    /// Default to an empty prefix, in which case all the variables are used.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the separator between the tokens of a variable name, each token
+    /// becomes a level of the nested document.
+    ///
+    /// Default to `__`, so that a single `_` can still be part of a key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `separator` is empty.
+    pub fn separator(mut self, separator: impl Into<String>) -> Self {
+        self.separator = separator.into();
+        assert!(!self.separator.is_empty(), "separator cannot be empty");
+        self
+    }
+
+    /// If set to `true`, variable names are lowercased, e.g., `CLUSTER__NAME`
+    /// becomes `cluster.name`.
+    ///
+    /// Default to `true`.
+    pub fn lowercase(mut self, lowercase: bool) -> Self {
+        self.lowercase = lowercase;
+        self
+    }
+
+    /// If set to `true`, values are resolved with the YAML 1.2 core schema,
+    /// see [`infer_scalar()`], so that `true`, `128`, `0.5` and `null` become
+    /// a boolean, numbers and null. Other values, e.g., `[a, b]` or `yes`,
+    /// are kept as strings, and so are empty values, e.g., `FOO=`.
+    ///
+    /// Default to `false`, in which case every value is a string.
+    pub fn infer_types(mut self, infer_types: bool) -> Self {
+        self.infer_types = infer_types;
+        self
+    }
+
+    /// Sets what [`overlay_env()`] does when 2 keys conflict, see
+    /// [`UnflattenOptions::on_conflict()`].
+    ///
+    /// Default to [`ConflictPolicy::LastWins`], so that the variables
+    /// override the document.
+    pub fn on_conflict(mut self, on_conflict: ConflictPolicy) -> Self {
+        self.on_conflict = on_conflict;
+        self
+    }
+}
+
+/// Turns the environment variables `vars` into dotted keys and their values
+/// according to `options`, in the order they are given.
+///
+/// `vars` can be [`std::env::vars()`] or any other name and value pairs.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::env::parse_env;
+/// # use serde_yaml_nested::env::EnvOptions;
+/// let vars = [("ES_NODE__MAX_SHARDS", "10"), ("PATH", "/usr/bin")];
+///
+/// let options = EnvOptions::new().prefix("ES_").infer_types(true);
+/// assert_eq!(
+///     parse_env(vars, &options),
+///     vec![(String::from("node.max_shards"), Value::Number(10.into()))]
+/// );
+/// ```
+pub fn parse_env<I, K, V>(vars: I, options: &EnvOptions) -> Vec<(String, Value)>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    vars.into_iter()
+        .filter_map(|(name, value)| {
+            let name = name.as_ref().strip_prefix(&options.prefix)?;
+            if name.is_empty() {
+                return None;
+            }
+
+            let tokens: Vec<String> = name
+                .split(&options.separator)
+                .map(|token| match options.lowercase {
+                    true => token.to_lowercase(),
+                    false => token.to_string(),
+                })
+                .collect();
+            Some((tokens.join("."), parse_value(value.as_ref(), options)))
+        })
+        .collect()
+}
+
+fn parse_value(value: &str, options: &EnvOptions) -> Value {
+    // An empty variable is set to an empty string rather than to null.
+    if !options.infer_types || value.is_empty() {
+        return Value::String(value.to_string());
+    }
+
+    infer_scalar(value).unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Overlays the environment variables `vars` onto `input`.
+///
+/// The variables are turned into dotted keys by [`parse_env()`], then set
+/// one by one into `input` like [`set_path()`] does, with
+/// [`EnvOptions::on_conflict()`] deciding what to do when a variable sets a
+/// value that already exists, or a value under one that is not a mapping. So
+/// by default, `CLUSTER=x` replaces the whole `cluster` mapping, and
+/// `CLUSTER__NAME=x` turns a `cluster` scalar into a mapping.
+///
+/// `input` is first normalized by [`normalize_nested()`], so its dotted keys
+/// are split, while the types of the keys and the empty mappings are kept.
+/// Sequences are replaced as a whole. An empty document is replaced by the
+/// variables, any other `input` that is not a mapping conflicts with them.
+///
+/// # Errors
+///
+/// Returns [`Error::DuplicateValue`] if a variable conflicts and the policy
+/// is [`ConflictPolicy::Error`], or all these errors in
+/// [`Error::Conflicts`] if the policy is [`ConflictPolicy::CollectAll`], and
+/// the errors of [`normalize_nested()`] if `input` cannot be normalized.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::env::overlay_env;
+/// # use serde_yaml_nested::env::EnvOptions;
+/// let settings: Value = from_str("cluster: {name: es, routing.allocation.enable: all}").unwrap();
+/// let vars = [("ES_CLUSTER__ROUTING__ALLOCATION__ENABLE", "none")];
+///
+/// let settings = overlay_env(settings, vars, &EnvOptions::new().prefix("ES_")).unwrap();
+/// let expected: Value =
+///     from_str("cluster: {name: es, routing: {allocation: {enable: none}}}").unwrap();
+/// assert_eq!(settings, expected);
+/// ```
+pub fn overlay_env<I, K, V>(input: Value, vars: I, options: &EnvOptions) -> Result<Value, Error>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let mut overlaid = normalize_nested(input)?;
+    let mut errors = Vec::new();
+    for (key, value) in parse_env(vars, options) {
+        let path = Path::parse_with(&key, &UnflattenOptions::new());
+        let prior = match get_path(&overlaid, &path) {
+            Some(_) => Some(path.clone()),
+            None => blocking_path(&overlaid, &path),
+        };
+
+        if let Some(prior) = prior {
+            // The tokens are those of the variable, like unflatten() reports.
+            let tokens: Vec<&str> = key.split('.').take(prior.len()).collect();
+            let error = Error::DuplicateValue {
+                token: tokens.last().copied().unwrap_or_default().to_string(),
+                prior: tokens.join("."),
+                key,
+            };
+            match options.on_conflict {
+                ConflictPolicy::Error => return Err(error),
+                ConflictPolicy::FirstWins => continue,
+                ConflictPolicy::LastWins => {
+                    set_path(&mut overlaid, &prior, Value::Null)?;
+                }
+                ConflictPolicy::CollectAll => {
+                    errors.push(error);
+                    continue;
+                }
+            }
+        }
+
+        set_path(&mut overlaid, &path, value)?;
+    }
+
+    if !errors.is_empty() {
+        return Err(Error::Conflicts(errors));
+    }
+    Ok(overlaid)
+}
+
+/// Returns the path to the value in the way of setting `path` in `value`,
+/// i.e., the first value on the way that is neither a mapping nor null.
+fn blocking_path(value: &Value, path: &Path) -> Option<Path> {
+    let mut current = value;
+    let mut prefix = Path::new();
+    for segment in path {
+        current = match current {
+            Value::Mapping(mapping) => mapping.get(find_key(mapping, segment)?)?,
+            Value::Null => return None,
+            _ => return Some(prefix),
+        };
+        prefix.push(segment.clone());
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_parse_env() {
+        let vars = vec![
+            (String::from("APP_Log-Level"), String::from("debug")),
+            (String::from("APP_"), String::from("ignored")),
+            (String::from("app_name"), String::from("ignored")),
+            (String::from("APP_A::B::C"), String::from("~")),
+            (String::from("APP_D"), String::from("[x, y]")),
+            (String::from("APP_E"), String::from("1.5")),
+            (String::from("APP_F"), String::from("yes")),
+            (String::from("APP_G"), String::new()),
+        ];
+
+        let options = EnvOptions::new()
+            .prefix("APP_")
+            .separator("::")
+            .lowercase(false)
+            .infer_types(true);
+        assert_eq!(
+            parse_env(vars.clone(), &options),
+            vec![
+                (String::from("Log-Level"), Value::String("debug".into())),
+                (String::from("A.B.C"), Value::Null),
+                (String::from("D"), Value::String("[x, y]".into())),
+                (String::from("E"), Value::Number(1.5.into())),
+                (String::from("F"), Value::String("yes".into())),
+                (String::from("G"), Value::String(String::new())),
+            ]
+        );
+
+        let options = EnvOptions::new().prefix("APP_").separator("::");
+        assert_eq!(
+            parse_env(vars, &options)[1],
+            (String::from("a.b.c"), Value::String("~".into()))
+        );
+    }
+
+    #[test]
+    fn test_overlay_env() {
+        let input: Value = from_str("{a: {b: 1, c: [x]}, d: 2}").unwrap();
+        let vars = [("A__B", "3"), ("A__C", "y"), ("D__E", "4"), ("F", "5")];
+
+        let overlaid = overlay_env(input.clone(), vars, &EnvOptions::new()).unwrap();
+        let expected: Value = from_str(r#"{a: {b: "3", c: y}, d: {e: "4"}, f: "5"}"#).unwrap();
+        assert_eq!(overlaid, expected);
+
+        let options = EnvOptions::new().on_conflict(ConflictPolicy::Error);
+        assert_eq!(
+            overlay_env(input.clone(), [("A__B", "3")], &options),
+            Err(Error::DuplicateValue {
+                key: String::from("a.b"),
+                token: String::from("b"),
+                prior: String::from("a.b"),
+            })
+        );
+
+        let options = EnvOptions::new().on_conflict(ConflictPolicy::FirstWins);
+        let overlaid = overlay_env(input, [("A__B", "3"), ("G", "6")], &options).unwrap();
+        let expected: Value = from_str(r#"{a: {b: 1, c: [x]}, d: 2, g: "6"}"#).unwrap();
+        assert_eq!(overlaid, expected);
+
+        let overlaid = overlay_env(Value::Null, [("A", "1")], &EnvOptions::new()).unwrap();
+        assert_eq!(overlaid, from_str::<Value>(r#"a: "1""#).unwrap());
+    }
+
+    #[test]
+    fn test_overlay_env_keeps_input() {
+        let input: Value = from_str("{a: {}, 1: x, b: [y]}").unwrap();
+        let no_vars: [(&str, &str); 0] = [];
+        assert_eq!(
+            overlay_env(input.clone(), no_vars, &EnvOptions::new()).unwrap(),
+            input
+        );
+
+        let vars = [("A__B", "1"), ("1", "z")];
+        let overlaid = overlay_env(input.clone(), vars, &EnvOptions::new()).unwrap();
+        let expected: Value = from_str(r#"{a: {b: "1"}, 1: z, b: [y]}"#).unwrap();
+        assert_eq!(overlaid, expected);
+
+        let options = EnvOptions::new().on_conflict(ConflictPolicy::CollectAll);
+        let vars = [("B__C", "1"), ("1", "z"), ("D", "2")];
+        assert_eq!(
+            overlay_env(input, vars, &options),
+            Err(Error::Conflicts(vec![
+                Error::DuplicateValue {
+                    key: String::from("b.c"),
+                    token: String::from("b"),
+                    prior: String::from("b"),
+                },
+                Error::DuplicateValue {
+                    key: String::from("1"),
+                    token: String::from("1"),
+                    prior: String::from("1"),
+                },
+            ]))
+        );
+
+        let scalar = Value::String("x".into());
+        let options = EnvOptions::new().on_conflict(ConflictPolicy::FirstWins);
+        assert_eq!(
+            overlay_env(scalar.clone(), [("A", "1")], &options).unwrap(),
+            scalar
+        );
+    }
+}
@@ -0,0 +1,565 @@
+//! This module provides the conversions between [`Path`]s and JSON Pointers
+//! (RFC 6901), and applies JSON Patch (RFC 6902) and JSON Merge Patch
+//! (RFC 7386) documents to YAML values.
+//!
+//! JSON documents are valid YAML, so the patches are given as [`Value`]s,
+//! e.g., parsed by [`serde_yaml_ng::from_str()`].
+
+use crate::conversion::key_to_token;
+use crate::conversion::parse_index;
+use crate::conversion::Error;
+use crate::path::find_key;
+use crate::path::Path;
+use crate::path::PathSegment;
+use serde_yaml_ng::Mapping;
+use serde_yaml_ng::Number;
+use serde_yaml_ng::Value;
+
+/// Converts `path` to a JSON Pointer, escaping `~` as `~0` and `/` as `~1`.
+///
+/// Dotted keys can be converted by parsing them into a [`Path`] first, e.g.,
+/// with [`Path::parse_with()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_nested::json::path_to_pointer;
+/// # use serde_yaml_nested::path::Path;
+/// assert_eq!(path_to_pointer(&Path::parse("a/b.0.c~d")), "/a~1b/0/c~0d");
+/// assert_eq!(path_to_pointer(&Path::new()), "");
+/// ```
+pub fn path_to_pointer(path: &Path) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            PathSegment::Key(key) => format!("/{}", key.replace('~', "~0").replace('/', "~1")),
+            PathSegment::Index(index) => format!("/{}", index),
+        })
+        .collect()
+}
+
+/// Converts a JSON Pointer to a [`Path`], tokens that are sequence indexes
+/// become index segments, which match number and string keys as well, see
+/// [`get_path()`](crate::path::get_path).
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidPointer`] if `pointer` is neither empty nor
+/// starts with `/`, or it contains a `~` that is not followed by `0` or `1`.
+pub fn pointer_to_path(pointer: &str) -> Result<Path, Error> {
+    Ok(parse_pointer(pointer)?
+        .into_iter()
+        .map(|token| match parse_index(&token) {
+            Some(index) => PathSegment::Index(index),
+            None => PathSegment::Key(token),
+        })
+        .collect())
+}
+
+/// Splits `pointer` into unescaped tokens.
+fn parse_pointer(pointer: &str) -> Result<Vec<String>, Error> {
+    let invalid = || Error::InvalidPointer {
+        pointer: pointer.to_string(),
+    };
+    if pointer.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = pointer.strip_prefix('/').ok_or_else(invalid)?;
+
+    rest.split('/')
+        .map(|escaped| {
+            let mut token = String::with_capacity(escaped.len());
+            let mut chars = escaped.chars();
+            while let Some(char) = chars.next() {
+                match char {
+                    '~' => match chars.next() {
+                        Some('0') => token.push('~'),
+                        Some('1') => token.push('/'),
+                        _ => return Err(invalid()),
+                    },
+                    char => token.push(char),
+                }
+            }
+            Ok(token)
+        })
+        .collect()
+}
+
+/// Applies a JSON Patch (RFC 6902) to `value`.
+///
+/// `patch` should be a sequence of operations, each of which is a mapping
+/// with `op` and `path`, and `value` or `from` depending on `op`. Mapping
+/// keys are matched like [`get_path()`](crate::path::get_path) does, new
+/// keys are added as strings.
+///
+/// # Errors
+///
+/// Returns [`Error::InvalidPatch`] if an operation is malformed,
+/// [`Error::InvalidPointer`] if a pointer is malformed,
+/// [`Error::PathNotFound`] if a pointer refers to nothing, and
+/// [`Error::TestFailed`] if a `test` operation fails. `value` is not changed
+/// if any operation fails.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::json::apply_json_patch;
+/// let mut value: Value = from_str(r#"{"cluster": {"hosts": ["a"]}}"#).unwrap();
+/// let patch: Value = from_str(
+///     r#"[
+///         {"op": "add", "path": "/cluster/hosts/-", "value": "b"},
+///         {"op": "copy", "from": "/cluster/hosts", "path": "/seeds"}
+///     ]"#,
+/// )
+/// .unwrap();
+///
+/// apply_json_patch(&mut value, &patch).unwrap();
+/// let expected: Value = from_str("{cluster: {hosts: [a, b]}, seeds: [a, b]}").unwrap();
+/// assert_eq!(value, expected);
+/// ```
+pub fn apply_json_patch(value: &mut Value, patch: &Value) -> Result<(), Error> {
+    let operations = patch.as_sequence().ok_or_else(|| Error::InvalidPatch {
+        index: 0,
+        reason: String::from("the patch is not a sequence of operations"),
+    })?;
+
+    let mut patched = value.clone();
+    for (index, operation) in operations.iter().enumerate() {
+        apply_operation(&mut patched, index, operation)?;
+    }
+
+    *value = patched;
+    Ok(())
+}
+
+/// Applies the `index`th `operation` of a JSON Patch.
+fn apply_operation(value: &mut Value, index: usize, operation: &Value) -> Result<(), Error> {
+    let invalid = |reason: &str| Error::InvalidPatch {
+        index,
+        reason: reason.to_string(),
+    };
+    let field = |name: &str| {
+        operation
+            .get(name)
+            .ok_or_else(|| invalid(&format!("'{}' is missing", name)))
+    };
+    let string_field = |name: &str| {
+        field(name)?
+            .as_str()
+            .ok_or_else(|| invalid(&format!("'{}' is not a string", name)))
+    };
+
+    if !operation.is_mapping() {
+        return Err(invalid("the operation is not a mapping"));
+    }
+    let op = string_field("op")?;
+    let pointer = string_field("path")?;
+    let path = parse_pointer(pointer)?;
+    match op {
+        "add" => add(value, &path, field("value")?.clone(), pointer),
+        "remove" if path.is_empty() => Err(invalid("the whole document cannot be removed")),
+        "remove" => remove(value, &path, pointer).map(drop),
+        "replace" => {
+            let new_value = field("value")?.clone();
+            let target = get_mut(value, &path).ok_or_else(|| not_found(pointer))?;
+            *target = new_value;
+            Ok(())
+        }
+        "move" => {
+            let from_pointer = string_field("from")?;
+            let from = parse_pointer(from_pointer)?;
+            if from.is_empty() || (path.starts_with(&from) && path.len() > from.len()) {
+                return Err(invalid("a value cannot be moved into itself"));
+            }
+            let moved = remove(value, &from, from_pointer)?;
+            add(value, &path, moved, pointer)
+        }
+        "copy" => {
+            let from_pointer = string_field("from")?;
+            let from = parse_pointer(from_pointer)?;
+            let copied = get(value, &from).ok_or_else(|| not_found(from_pointer))?;
+            add(value, &path, copied.clone(), pointer)
+        }
+        "test" => match get(value, &path) {
+            Some(actual) if json_equal(actual, field("value")?) => Ok(()),
+            Some(_) => Err(Error::TestFailed {
+                path: pointer.to_string(),
+            }),
+            None => Err(not_found(pointer)),
+        },
+        op => Err(invalid(&format!("'{}' is not a known operation", op))),
+    }
+}
+
+fn not_found(pointer: &str) -> Error {
+    Error::PathNotFound {
+        path: pointer.to_string(),
+    }
+}
+
+fn get<'a>(value: &'a Value, tokens: &[String]) -> Option<&'a Value> {
+    tokens.iter().try_fold(value, |value, token| match value {
+        Value::Mapping(mapping) => mapping.get(find_key(mapping, &key_segment(token))?),
+        Value::Sequence(sequence) => sequence.get(parse_index(token)?),
+        _ => None,
+    })
+}
+
+fn get_mut<'a>(value: &'a mut Value, tokens: &[String]) -> Option<&'a mut Value> {
+    tokens.iter().try_fold(value, |value, token| match value {
+        Value::Mapping(mapping) => {
+            let key = find_key(mapping, &key_segment(token))?;
+            mapping.get_mut(key)
+        }
+        Value::Sequence(sequence) => sequence.get_mut(parse_index(token)?),
+        _ => None,
+    })
+}
+
+/// The `add` operation, which inserts `new_value` into a sequence, or sets
+/// it in a mapping.
+fn add(value: &mut Value, tokens: &[String], new_value: Value, pointer: &str) -> Result<(), Error> {
+    let Some((last, parents)) = tokens.split_last() else {
+        *value = new_value;
+        return Ok(());
+    };
+
+    match get_mut(value, parents).ok_or_else(|| not_found(pointer))? {
+        Value::Mapping(mapping) => {
+            let key = find_key(mapping, &key_segment(last))
+                .unwrap_or_else(|| Value::String(last.clone()));
+            mapping.insert(key, new_value);
+        }
+        Value::Sequence(sequence) => {
+            let index = match last.as_str() {
+                "-" => sequence.len(),
+                last => parse_index(last)
+                    .filter(|index| *index <= sequence.len())
+                    .ok_or_else(|| not_found(pointer))?,
+            };
+            sequence.insert(index, new_value);
+        }
+        _ => return Err(not_found(pointer)),
+    }
+
+    Ok(())
+}
+
+/// The `remove` operation, `tokens` should not be empty.
+fn remove(value: &mut Value, tokens: &[String], pointer: &str) -> Result<Value, Error> {
+    let (last, parents) = tokens.split_last().expect("the root cannot be removed");
+    match get_mut(value, parents).ok_or_else(|| not_found(pointer))? {
+        Value::Mapping(mapping) => {
+            let key = find_key(mapping, &key_segment(last)).ok_or_else(|| not_found(pointer))?;
+            Ok(mapping.shift_remove(key).expect("the key is just found"))
+        }
+        Value::Sequence(sequence) => {
+            let index = parse_index(last)
+                .filter(|index| *index < sequence.len())
+                .ok_or_else(|| not_found(pointer))?;
+            Ok(sequence.remove(index))
+        }
+        _ => Err(not_found(pointer)),
+    }
+}
+
+fn key_segment(token: &str) -> PathSegment {
+    PathSegment::Key(token.to_string())
+}
+
+/// Compares `left` and `right` like the `test` operation does, i.e., numbers
+/// are equal if they have the same value, e.g., `1` and `1.0`.
+fn json_equal(left: &Value, right: &Value) -> bool {
+    let mut pending = vec![(left, right)];
+    while let Some(pair) = pending.pop() {
+        match pair {
+            (Value::Number(left), Value::Number(right)) => {
+                if !numbers_equal(left, right) {
+                    return false;
+                }
+            }
+            (Value::Sequence(left), Value::Sequence(right)) if left.len() == right.len() => {
+                pending.extend(left.iter().zip(right));
+            }
+            (Value::Mapping(left), Value::Mapping(right)) if left.len() == right.len() => {
+                for (key, left) in left {
+                    let Some(right) = right.get(key) else {
+                        return false;
+                    };
+                    pending.push((left, right));
+                }
+            }
+            (Value::Tagged(left), Value::Tagged(right)) if left.tag == right.tag => {
+                pending.push((&left.value, &right.value));
+            }
+            (left, right) => {
+                if left != right {
+                    return false;
+                }
+            }
+        }
+    }
+
+    true
+}
+
+fn numbers_equal(left: &Number, right: &Number) -> bool {
+    let as_integer = |number: &Number| {
+        number
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| number.as_u64().map(i128::from))
+    };
+    match (as_integer(left), as_integer(right)) {
+        (Some(left), Some(right)) => left == right,
+        _ => left.as_f64() == right.as_f64(),
+    }
+}
+
+/// Applies a JSON Merge Patch (RFC 7386) to `value`.
+///
+/// If `patch` is a mapping, its keys are merged into `value` recursively, a
+/// null value removes the key, otherwise `patch` replaces `value`. Merging
+/// a mapping into a value that is not a mapping replaces it as well. Keys are
+/// matched like the tokens of [`apply_json_patch()`], e.g., `"1"` matches
+/// the number key `1`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::json::apply_merge_patch;
+/// let mut value: Value = from_str("{a: b, c: {d: e, f: g}}").unwrap();
+/// let patch: Value = from_str("{a: z, c: {f: null}}").unwrap();
+///
+/// apply_merge_patch(&mut value, &patch);
+/// let expected: Value = from_str("{a: z, c: {d: e}}").unwrap();
+/// assert_eq!(value, expected);
+/// ```
+pub fn apply_merge_patch(value: &mut Value, patch: &Value) {
+    // The keys leading to the values still to be patched, and their patches.
+    let mut pending: Vec<(Vec<Value>, &Value)> = vec![(Vec::new(), patch)];
+    while let Some((keys, patch)) = pending.pop() {
+        let target = keys.iter().fold(&mut *value, |target, key| {
+            target
+                .as_mapping_mut()
+                .and_then(|mapping| mapping.get_mut(key))
+                .expect("the key is inserted before its patch")
+        });
+        let Value::Mapping(patch) = patch else {
+            *target = patch.clone();
+            continue;
+        };
+        if !target.is_mapping() {
+            *target = Value::Mapping(Mapping::new());
+        }
+        let Value::Mapping(target) = target else {
+            unreachable!("it is just made a mapping")
+        };
+
+        for (key, patch_value) in patch {
+            // The keys are matched like JSON Pointer tokens, e.g., "1" is 1.
+            let key = key_to_token(key.clone(), String::new)
+                .ok()
+                .and_then(|token| find_key(target, &key_segment(&token)))
+                .unwrap_or_else(|| key.clone());
+            if patch_value.is_null() {
+                target.shift_remove(&key);
+            } else {
+                target.entry(key.clone()).or_insert(Value::Null);
+                let mut keys = keys.clone();
+                keys.push(key);
+                pending.push((keys, patch_value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_pointers() {
+        // The examples of RFC 6901.
+        let value: Value = from_str(
+            r#"{
+                "foo": ["bar", "baz"],
+                "": 0,
+                "a/b": 1,
+                "c%d": 2,
+                "e^f": 3,
+                "g|h": 4,
+                "i\\j": 5,
+                "k\"l": 6,
+                " ": 7,
+                "m~n": 8
+            }"#,
+        )
+        .unwrap();
+        let cases = [
+            ("", value.clone()),
+            ("/foo", from_str(r#"["bar", "baz"]"#).unwrap()),
+            ("/foo/0", Value::String("bar".into())),
+            ("/", Value::Number(0.into())),
+            ("/a~1b", Value::Number(1.into())),
+            ("/c%d", Value::Number(2.into())),
+            ("/e^f", Value::Number(3.into())),
+            ("/g|h", Value::Number(4.into())),
+            ("/i\\j", Value::Number(5.into())),
+            ("/k\"l", Value::Number(6.into())),
+            ("/ ", Value::Number(7.into())),
+            ("/m~0n", Value::Number(8.into())),
+        ];
+        for (pointer, expected) in cases {
+            let tokens = parse_pointer(pointer).unwrap();
+            assert_eq!(get(&value, &tokens), Some(&expected), "{}", pointer);
+
+            let path = pointer_to_path(pointer).unwrap();
+            assert_eq!(path_to_pointer(&path), pointer);
+        }
+
+        for pointer in ["foo", "/~2", "/a~"] {
+            assert_eq!(
+                pointer_to_path(pointer),
+                Err(Error::InvalidPointer {
+                    pointer: pointer.to_string(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_json_patch() {
+        let mut value: Value = from_str("{a: {b: [1, 2]}, c: x, 1: one}").unwrap();
+        let patch: Value = from_str(
+            r#"[
+                {"op": "test", "path": "/1", "value": "one"},
+                {"op": "test", "path": "/a", "value": {"b": [1.0, 2e0]}},
+                {"op": "add", "path": "/a/b/1", "value": 3},
+                {"op": "remove", "path": "/a/b/0"},
+                {"op": "replace", "path": "/c", "value": {"d": "y"}},
+                {"op": "move", "from": "/c/d", "path": "/a/e"},
+                {"op": "copy", "from": "/a/b", "path": "/f"},
+                {"op": "add", "path": "", "value": null},
+                {"op": "add", "path": "", "value": {"g": [0]}}
+            ]"#,
+        )
+        .unwrap();
+        let original = value.clone();
+
+        let mut steps = value.clone();
+        for (index, operation) in patch.as_sequence().unwrap().iter().enumerate().take(7) {
+            apply_operation(&mut steps, index, operation).unwrap();
+        }
+        let expected: Value = from_str("{a: {b: [3, 2], e: y}, c: {}, 1: one, f: [3, 2]}").unwrap();
+        assert_eq!(steps, expected);
+
+        apply_json_patch(&mut value, &patch).unwrap();
+        assert_eq!(value, from_str::<Value>("g: [0]").unwrap());
+
+        let mut value = original;
+        let errors = [
+            (
+                r#"[{"op": "test", "path": "/a/b", "value": [1, 2.5]}]"#,
+                Error::TestFailed {
+                    path: String::from("/a/b"),
+                },
+            ),
+            (
+                r#"[{"op": "test", "path": "/c", "value": "y"}]"#,
+                Error::TestFailed {
+                    path: String::from("/c"),
+                },
+            ),
+            (
+                r#"[{"op": "add", "path": "/a/b/3", "value": 0}]"#,
+                Error::PathNotFound {
+                    path: String::from("/a/b/3"),
+                },
+            ),
+            (
+                r#"[{"op": "remove", "path": "/x"}]"#,
+                Error::PathNotFound {
+                    path: String::from("/x"),
+                },
+            ),
+            (
+                r#"[{"op": "move", "from": "/a", "path": "/a/b/0"}]"#,
+                Error::InvalidPatch {
+                    index: 0,
+                    reason: String::from("a value cannot be moved into itself"),
+                },
+            ),
+            (
+                r#"[{"op": "remove", "path": "/c"}, {"op": "replace", "path": "/c"}]"#,
+                Error::InvalidPatch {
+                    index: 1,
+                    reason: String::from("'value' is missing"),
+                },
+            ),
+            (
+                r#"[{"op": "merge", "path": "/c"}]"#,
+                Error::InvalidPatch {
+                    index: 0,
+                    reason: String::from("'merge' is not a known operation"),
+                },
+            ),
+        ];
+        for (patch, error) in errors {
+            let patch: Value = from_str(patch).unwrap();
+            let before = value.clone();
+            assert_eq!(apply_json_patch(&mut value, &patch), Err(error));
+            assert_eq!(value, before);
+        }
+    }
+
+    #[test]
+    fn test_apply_merge_patch() {
+        // The example of RFC 7386.
+        let mut value: Value = from_str(
+            r#"{
+                "title": "Goodbye!",
+                "author": {"givenName": "John", "familyName": "Doe"},
+                "tags": ["example", "sample"],
+                "content": "This will be unchanged"
+            }"#,
+        )
+        .unwrap();
+        let patch: Value = from_str(
+            r#"{
+                "title": "Hello!",
+                "phoneNumber": "+01-123-456-7890",
+                "author": {"familyName": null},
+                "tags": ["example"]
+            }"#,
+        )
+        .unwrap();
+        let expected: Value = from_str(
+            r#"{
+                "title": "Hello!",
+                "author": {"givenName": "John"},
+                "tags": ["example"],
+                "content": "This will be unchanged",
+                "phoneNumber": "+01-123-456-7890"
+            }"#,
+        )
+        .unwrap();
+
+        apply_merge_patch(&mut value, &patch);
+        assert_eq!(value, expected);
+
+        let mut value = Value::String("x".into());
+        apply_merge_patch(&mut value, &from_str("{a: {b: null, c: 1}}").unwrap());
+        assert_eq!(value, from_str::<Value>("a: {c: 1}").unwrap());
+
+        let mut value: Value = from_str("{1: a, 2: b, true: {3: c}}").unwrap();
+        let patch: Value = from_str(r#"{"1": null, "2": x, "true": {"3": y}}"#).unwrap();
+        apply_merge_patch(&mut value, &patch);
+        assert_eq!(value, from_str::<Value>("{2: x, true: {3: y}}").unwrap());
+    }
+}
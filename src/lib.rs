@@ -1 +1,8 @@
 pub mod conversion;
+pub mod diff;
+pub mod env;
+pub mod json;
+pub mod merge;
+pub mod origin;
+pub mod patch;
+pub mod path;
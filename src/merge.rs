@@ -0,0 +1,666 @@
+//! This module provides [`merge()`] and [`merge_layers()`] to deep merge
+//! layered YAML configurations, e.g., defaults, cluster-level and node-level
+//! settings.
+//!
+//! [`Merged`] tells which layer sets each leaf and which layers it overrides,
+//! and with [`merge_documents()`], where they are written.
+
+use crate::conversion::key_to_token;
+use crate::conversion::normalize_nested;
+use crate::conversion::Error;
+use crate::origin::Document;
+use crate::origin::Locations;
+use crate::origin::Origin;
+use crate::path::get_path;
+use crate::path::get_path_mut;
+use crate::path::Path;
+use crate::path::PathSegment;
+use serde_yaml_ng::Value;
+use std::cmp::min;
+use std::collections::BTreeMap;
+
+/// How two sequences at the same path are combined by [`merge()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SequenceMerge {
+    /// The overlay sequence replaces the base one as a whole.
+    #[default]
+    Replace,
+    /// The overlay elements are appended to the base ones.
+    Append,
+    /// Elements at the same index are merged, extra overlay elements are
+    /// appended.
+    ByIndex,
+}
+
+/// Options to customize the behavior of [`merge()`] and [`merge_layers()`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergePolicy {
+    sequences: SequenceMerge,
+}
+
+impl MergePolicy {
+    /// Creates the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how two sequences at the same path are combined.
+    ///
+    /// Default to [`SequenceMerge::Replace`].
+    pub fn sequences(mut self, sequences: SequenceMerge) -> Self {
+        self.sequences = sequences;
+        self
+    }
+}
+
+/// The result of [`merge_layers()`], which is the merged value and the layer
+/// each of its leaves comes from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Merged {
+    value: Value,
+    origins: BTreeMap<Path, usize>,
+    /// The layers whose leaves are overridden by the leaf at the same path,
+    /// oldest first.
+    overridden: BTreeMap<Path, Vec<usize>>,
+    /// The positions in each layer, empty unless merged by
+    /// [`merge_documents()`].
+    locations: Vec<Locations>,
+    /// The paths of the appended sequence elements in their layers, see
+    /// [`SequenceMerge::Append`].
+    appended: BTreeMap<Path, Path>,
+}
+
+impl Merged {
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+
+    /// Returns the index of the layer that sets the value at `path`, or the
+    /// leaf containing it, e.g., a sequence that is replaced as a whole.
+    ///
+    /// Returns `None` if no leaf is at or above `path`.
+    pub fn origin(&self, path: impl Into<Path>) -> Option<usize> {
+        self.leaf(path.into()).map(|(_, layer)| layer)
+    }
+
+    /// Returns where the value at `path`, or the leaf containing it, is
+    /// written, see [`Self::origin()`].
+    ///
+    /// Returns `None` if no leaf is at or above `path`, or if the layers are
+    /// not merged by [`merge_documents()`].
+    pub fn source(&self, path: impl Into<Path>) -> Option<Origin> {
+        let (leaf, layer) = self.leaf(path.into())?;
+        self.locations.get(layer)?.origin(&self.layer_path(leaf))
+    }
+
+    /// Returns the path of `leaf` in the layer it comes from.
+    fn layer_path(&self, leaf: Path) -> Path {
+        let segments = leaf.segments();
+        for len in (1..=segments.len()).rev() {
+            let prefix = Path::from(segments[..len].to_vec());
+            if let Some(appended) = self.appended.get(&prefix) {
+                return appended.iter().chain(&segments[len..]).cloned().collect();
+            }
+        }
+
+        leaf
+    }
+
+    /// Returns the leaf at or above `path` and the layer it comes from.
+    fn leaf(&self, mut path: Path) -> Option<(Path, usize)> {
+        loop {
+            if let Some(layer) = self.origins.get(&path) {
+                return Some((path, *layer));
+            }
+            path.pop()?;
+        }
+    }
+
+    /// Returns the paths of all the leaves and the indexes of the layers
+    /// they come from.
+    ///
+    /// Sequences are leaves if they are combined with
+    /// [`SequenceMerge::Replace`], otherwise their elements are.
+    pub fn origins(&self) -> &BTreeMap<Path, usize> {
+        &self.origins
+    }
+
+    /// Returns the indexes of the layers that set a leaf at exactly `path`
+    /// before it is overridden, oldest first.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// # use serde_yaml_ng::from_str;
+    /// # use serde_yaml_ng::Value;
+    /// # use serde_yaml_nested::merge::merge_layers;
+    /// # use serde_yaml_nested::merge::MergePolicy;
+    /// let names = ["defaults.yml", "cluster.yml", "node.yml"];
+    /// let layers: Vec<Value> = ["cluster.name: a", "cluster: {name: b}", "cluster.name: c"]
+    ///     .into_iter()
+    ///     .map(|layer| from_str(layer).unwrap())
+    ///     .collect();
+    ///
+    /// let merged = merge_layers(layers, &MergePolicy::new()).unwrap();
+    /// let overridden: Vec<&str> = merged
+    ///     .overridden("cluster.name")
+    ///     .iter()
+    ///     .map(|layer| names[*layer])
+    ///     .collect();
+    /// assert_eq!(
+    ///     format!(
+    ///         "cluster.name set by {}, overriding {}",
+    ///         names[merged.origin("cluster.name").unwrap()],
+    ///         overridden.join(", ")
+    ///     ),
+    ///     "cluster.name set by node.yml, overriding defaults.yml, cluster.yml"
+    /// );
+    /// ```
+    pub fn overridden(&self, path: impl Into<Path>) -> &[usize] {
+        self.overridden
+            .get(&path.into())
+            .map_or(&[], |layers| layers.as_slice())
+    }
+
+    /// Returns where the leaves overridden by the leaf at exactly `path` are
+    /// written, oldest first, see [`Self::overridden()`].
+    ///
+    /// Returns an empty list if the layers are not merged by
+    /// [`merge_documents()`].
+    pub fn overridden_sources(&self, path: impl Into<Path>) -> Vec<Origin> {
+        let path = path.into();
+        self.overridden(path.clone())
+            .iter()
+            .filter_map(|layer| self.locations.get(*layer)?.origin(&path))
+            .collect()
+    }
+
+    /// Returns the leaves of the merged value with their paths and the
+    /// indexes of the layers they come from, ordered by the paths.
+    pub fn leaves(&self) -> impl Iterator<Item = (&Path, &Value, usize)> {
+        self.origins.iter().map(|(path, layer)| {
+            let value = get_path(&self.value, path).expect("leaves exist");
+            (path, value, *layer)
+        })
+    }
+}
+
+/// Deep merges `overlay` into `base`, the values in `overlay` win.
+///
+/// Dotted keys on both sides are split first, see
+/// [`normalize_nested()`](crate::conversion::normalize_nested), so that
+/// `cluster.name: x` in `overlay` overrides `cluster: {name: y}` in `base`.
+/// Mappings are merged key by key, sequences are combined according to
+/// `policy`, and any other value in `overlay`, including tagged values and
+/// nulls, replaces what is in `base`.
+///
+/// # Errors
+///
+/// Returns the errors of
+/// [`normalize_nested()`](crate::conversion::normalize_nested) if either side
+/// has conflicting keys.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::merge::merge;
+/// # use serde_yaml_nested::merge::MergePolicy;
+/// # use serde_yaml_nested::merge::SequenceMerge;
+/// let base: Value = from_str("cluster: {name: es, hosts: [a]}").unwrap();
+/// let overlay: Value = from_str("{cluster.name: prod, cluster.hosts: [b]}").unwrap();
+///
+/// let policy = MergePolicy::new().sequences(SequenceMerge::Append);
+/// let merged = merge(base, overlay, &policy).unwrap();
+///
+/// let expected: Value = from_str("cluster: {name: prod, hosts: [a, b]}").unwrap();
+/// assert_eq!(merged, expected);
+/// ```
+pub fn merge(base: Value, overlay: Value, policy: &MergePolicy) -> Result<Value, Error> {
+    merge_layers([base, overlay], policy).map(Merged::into_value)
+}
+
+/// Merges the `layers` in order, each layer is merged into the result of
+/// the previous ones like [`merge()`] does, and records which layer each
+/// leaf of the result comes from. Layers are identified by their indexes.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::merge::merge_layers;
+/// # use serde_yaml_nested::merge::MergePolicy;
+/// let defaults: Value = from_str("cluster: {name: es, port: 9200}").unwrap();
+/// let cluster: Value = from_str("cluster.name: prod").unwrap();
+/// let node: Value = from_str("node.name: node-1").unwrap();
+///
+/// let merged = merge_layers([defaults, cluster, node], &MergePolicy::new()).unwrap();
+/// assert_eq!(merged.origin("cluster.name"), Some(1));
+/// assert_eq!(merged.origin("cluster.port"), Some(0));
+/// assert_eq!(merged.origin("node.name"), Some(2));
+/// ```
+pub fn merge_layers<I: IntoIterator<Item = Value>>(
+    layers: I,
+    policy: &MergePolicy,
+) -> Result<Merged, Error> {
+    let mut merged = Merged {
+        value: Value::Null,
+        origins: BTreeMap::new(),
+        overridden: BTreeMap::new(),
+        locations: Vec::new(),
+        appended: BTreeMap::new(),
+    };
+    for (layer, value) in layers.into_iter().enumerate() {
+        let value = normalize_nested(value)?;
+        if layer == 0 {
+            record(&mut merged.origins, Path::new(), &value, layer, policy)?;
+            merged.value = value;
+        } else {
+            merge_into(&mut merged, value, layer, policy)?;
+        }
+    }
+
+    Ok(merged)
+}
+
+/// Merges the parsed `documents` in order like [`merge_layers()`] does, and
+/// keeps where their values are written, see [`Merged::source()`].
+///
+/// # Errors
+///
+/// Returns the same errors as [`merge_layers()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_nested::merge::merge_documents;
+/// # use serde_yaml_nested::merge::MergePolicy;
+/// # use serde_yaml_nested::origin::Document;
+/// let documents = [
+///     Document::parse("defaults.yml", "\ncluster:\n  name: default").unwrap(),
+///     Document::parse("node.yml", "node.name: node-1\ncluster.name: prod").unwrap(),
+/// ];
+///
+/// let merged = merge_documents(documents, &MergePolicy::new()).unwrap();
+/// let source = merged.source("cluster.name").unwrap();
+/// let overridden = &merged.overridden_sources("cluster.name")[0];
+/// assert_eq!(
+///     format!(
+///         "cluster.name set by {}:{}, overriding {}:{}",
+///         source.document(),
+///         source.line(),
+///         overridden.document(),
+///         overridden.line(),
+///     ),
+///     "cluster.name set by node.yml:2, overriding defaults.yml:3"
+/// );
+/// ```
+pub fn merge_documents<I: IntoIterator<Item = Document>>(
+    documents: I,
+    policy: &MergePolicy,
+) -> Result<Merged, Error> {
+    let (values, locations): (Vec<Value>, Vec<Locations>) =
+        documents.into_iter().map(Document::into_normalized).unzip();
+    let mut merged = merge_layers(values, policy)?;
+    merged.locations = locations;
+
+    Ok(merged)
+}
+
+/// Merges `overlay` into `merged`.
+///
+/// The nested mappings and sequences are merged with an explicit stack
+/// rather than recursion.
+fn merge_into(
+    merged: &mut Merged,
+    overlay: Value,
+    layer: usize,
+    policy: &MergePolicy,
+) -> Result<(), Error> {
+    let mut pending = vec![(Path::new(), overlay)];
+    while let Some((path, overlay)) = pending.pop() {
+        let base = value_at(&mut merged.value, &path);
+        match (base, overlay) {
+            (Value::Mapping(base), Value::Mapping(overlay)) => {
+                let mut nested = Vec::new();
+                for (key, value) in overlay {
+                    let mut sub_path = path.clone();
+                    sub_path.push(segment(&key, &path)?);
+                    if base.contains_key(&key) {
+                        nested.push((sub_path, value));
+                    } else {
+                        record(&mut merged.origins, sub_path, &value, layer, policy)?;
+                        base.insert(key, value);
+                    }
+                }
+                // Reversed so that they are popped in order
+                pending.extend(nested.into_iter().rev());
+            }
+            (Value::Sequence(base), Value::Sequence(overlay))
+                if policy.sequences != SequenceMerge::Replace =>
+            {
+                let merged_len = match policy.sequences {
+                    SequenceMerge::ByIndex => min(base.len(), overlay.len()),
+                    _ => 0,
+                };
+                let mut nested = Vec::new();
+                for (index, value) in overlay.into_iter().enumerate() {
+                    let mut sub_path = path.clone();
+                    if index < merged_len {
+                        sub_path.push(PathSegment::Index(index));
+                        nested.push((sub_path, value));
+                    } else {
+                        sub_path.push(PathSegment::Index(base.len()));
+                        if base.len() != index {
+                            let mut layer_path = path.clone();
+                            layer_path.push(PathSegment::Index(index));
+                            merged.appended.insert(sub_path.clone(), layer_path);
+                        }
+                        record(&mut merged.origins, sub_path, &value, layer, policy)?;
+                        base.push(value);
+                    }
+                }
+                pending.extend(nested.into_iter().rev());
+            }
+            (base, overlay) => {
+                // The history is kept only if a leaf overrides a leaf.
+                let mut history = merged.overridden.remove(&path).unwrap_or_default();
+                history.extend(merged.origins.get(&path));
+
+                let replaced: Vec<Path> = merged
+                    .origins
+                    .range(path.clone()..)
+                    .map(|(leaf, _)| leaf)
+                    .take_while(|leaf| leaf.starts_with(&path))
+                    .cloned()
+                    .collect();
+                for leaf in replaced {
+                    merged.origins.remove(&leaf);
+                    merged.overridden.remove(&leaf);
+                }
+                merged
+                    .appended
+                    .retain(|appended, _| !appended.starts_with(&path));
+                record(&mut merged.origins, path.clone(), &overlay, layer, policy)?;
+                if merged.origins.contains_key(&path) && !history.is_empty() {
+                    merged.overridden.insert(path, history);
+                }
+                *base = overlay;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the value at `path`, which must exist.
+fn value_at<'a>(value: &'a mut Value, path: &Path) -> &'a mut Value {
+    get_path_mut(value, path).expect("merged paths exist")
+}
+
+/// Records `layer` as the origin of the leaves of `value` at `path`.
+///
+/// The nested mappings and sequences are walked with an explicit stack
+/// rather than recursion.
+fn record(
+    origins: &mut BTreeMap<Path, usize>,
+    path: Path,
+    value: &Value,
+    layer: usize,
+    policy: &MergePolicy,
+) -> Result<(), Error> {
+    let mut pending = vec![(path, value)];
+    while let Some((path, value)) = pending.pop() {
+        match value {
+            Value::Mapping(mapping) if !mapping.is_empty() => {
+                for (key, value) in mapping {
+                    let mut sub_path = path.clone();
+                    sub_path.push(segment(key, &path)?);
+                    pending.push((sub_path, value));
+                }
+            }
+            Value::Sequence(sequence)
+                if policy.sequences != SequenceMerge::Replace && !sequence.is_empty() =>
+            {
+                for (index, value) in sequence.iter().enumerate() {
+                    let mut sub_path = path.clone();
+                    sub_path.push(PathSegment::Index(index));
+                    pending.push((sub_path, value));
+                }
+            }
+            _ => {
+                origins.insert(path, layer);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a mapping `key` under `path` to a path segment.
+fn segment(key: &Value, path: &Path) -> Result<PathSegment, Error> {
+    key_to_token(key.clone(), || path.to_string()).map(PathSegment::Key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+    use serde_yaml_ng::Mapping;
+
+    #[test]
+    fn test_merge() {
+        let base: Value = from_str(
+            r#"
+cluster:
+  name: es
+  routing.allocation.enable: all
+node.roles: [master, data]
+tags: !list [a]
+"#,
+        )
+        .unwrap();
+        let overlay: Value = from_str(
+            r#"
+cluster.routing:
+  allocation: {enable: none}
+node:
+  roles: [ingest]
+  name: node-1
+tags: null
+"#,
+        )
+        .unwrap();
+
+        let merged = merge(base.clone(), overlay.clone(), &MergePolicy::new()).unwrap();
+        let expected: Value = from_str(
+            r#"
+cluster:
+  name: es
+  routing: {allocation: {enable: none}}
+node: {roles: [ingest], name: node-1}
+tags: null
+"#,
+        )
+        .unwrap();
+        assert_eq!(merged, expected);
+
+        let policy = MergePolicy::new().sequences(SequenceMerge::Append);
+        let merged = merge(base, overlay, &policy).unwrap();
+        assert_eq!(
+            merged["node"]["roles"],
+            from_str::<Value>("[master, data, ingest]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_merge_keeps_untouched_values() {
+        let base: Value = from_str("{a: {}, 1: x, b: 1, c: {d: {}}}").unwrap();
+        let overlay: Value = from_str("{b: 2, c.e: 3}").unwrap();
+
+        let merged = merge(base, overlay, &MergePolicy::new()).unwrap();
+        let expected: Value = from_str("{a: {}, 1: x, b: 2, c: {d: {}, e: 3}}").unwrap();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn test_merge_deeply_nested() {
+        let depth = 2_000;
+        let nested = |leaf: &str| {
+            let mut yaml = Value::String(leaf.into());
+            for _ in 0..depth {
+                let mut mapping = Mapping::new();
+                mapping.insert(Value::String("a".into()), yaml);
+                yaml = Value::Mapping(mapping);
+            }
+            yaml
+        };
+
+        let merged = merge_layers([nested("x"), nested("y")], &MergePolicy::new()).unwrap();
+        let path: Path = vec![PathSegment::from("a"); depth].into();
+        assert_eq!(merged.origin(path.clone()), Some(1));
+        assert_eq!(merged.overridden(path), [0]);
+
+        // The merged value is as deep as the layers, drop it on a thread with
+        // a stack large enough for its recursive drop.
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || drop(merged))
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_merge_documents() {
+        let documents = [
+            ("defaults.yml", "cluster:\n  name: es\n  hosts: [a]\n  1: x"),
+            ("cluster.yml", "cluster.hosts: [b, c]\ncluster.name: prod"),
+            ("node.yml", "cluster:\n  name: node-1"),
+        ]
+        .map(|(name, source)| Document::parse(name, source).unwrap());
+
+        let policy = MergePolicy::new().sequences(SequenceMerge::Append);
+        let merged = merge_documents(documents, &policy).unwrap();
+        let source = |path: &str| merged.source(path).map(|origin| origin.to_string());
+        assert_eq!(source("cluster.name"), Some(String::from("node.yml:2:3")));
+        assert_eq!(
+            source("cluster.hosts.0"),
+            Some(String::from("defaults.yml:3:11"))
+        );
+        assert_eq!(
+            source("cluster.hosts.1"),
+            Some(String::from("cluster.yml:1:17"))
+        );
+        assert_eq!(
+            source("cluster.hosts.2"),
+            Some(String::from("cluster.yml:1:20"))
+        );
+        assert_eq!(
+            source(r"cluster.\1"),
+            Some(String::from("defaults.yml:4:3"))
+        );
+        assert_eq!(source("cluster"), None);
+
+        let overridden: Vec<String> = merged
+            .overridden_sources("cluster.name")
+            .iter()
+            .map(Origin::to_string)
+            .collect();
+        assert_eq!(overridden, ["defaults.yml:2:3", "cluster.yml:2:1"]);
+
+        let merged = merge_layers([merged.into_value()], &policy).unwrap();
+        assert_eq!(merged.source("cluster.name"), None);
+        assert_eq!(merged.overridden_sources("cluster.name"), []);
+    }
+
+    #[test]
+    fn test_merge_sequences_by_index() {
+        let base: Value = from_str("a: [{b: 1, c: 2}, x]").unwrap();
+        let overlay: Value = from_str("a: [{b: 3}, y, z]").unwrap();
+
+        let policy = MergePolicy::new().sequences(SequenceMerge::ByIndex);
+        let merged = merge_layers([base, overlay], &policy).unwrap();
+        let expected: Value = from_str("a: [{b: 3, c: 2}, y, z]").unwrap();
+        assert_eq!(merged.value(), &expected);
+        assert_eq!(
+            merged.origins(),
+            &BTreeMap::from([
+                (Path::parse("a.0.b"), 1),
+                (Path::parse("a.0.c"), 0),
+                (Path::parse("a.1"), 1),
+                (Path::parse("a.2"), 1),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_merge_layers_origins() {
+        let layers: Vec<Value> = [
+            "{cluster: {name: es, hosts: [a, b]}, path.data: /data}",
+            "{cluster.hosts: [c], path: {data: {0: /d0}}}",
+            "{cluster: {name: prod}}",
+        ]
+        .into_iter()
+        .map(|layer| from_str(layer).unwrap())
+        .collect();
+
+        let merged = merge_layers(layers, &MergePolicy::new()).unwrap();
+        assert_eq!(
+            merged.origins(),
+            &BTreeMap::from([
+                (Path::parse("cluster.name"), 2),
+                (Path::parse("cluster.hosts"), 1),
+                (Path::parse(r"path.data.\0"), 1),
+            ])
+        );
+        assert_eq!(merged.origin("cluster.hosts.0"), Some(1));
+        assert_eq!(merged.origin("cluster"), None);
+
+        let error = merge_layers(
+            [Value::Null, from_str("{a.b: 1, a: {b: 2}}").unwrap()],
+            &MergePolicy::new(),
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::DuplicateValue { .. }));
+    }
+
+    #[test]
+    fn test_merge_layers_overridden() {
+        let layers: Vec<Value> = [
+            "{a: {b: 1, c: 1}, d: [x]}",
+            "{a.b: 2, a: {c: {e: 2}}, d: [y]}",
+            "{a: {b: 3, c: 3}}",
+        ]
+        .into_iter()
+        .map(|layer| from_str(layer).unwrap())
+        .collect();
+
+        let merged = merge_layers(layers, &MergePolicy::new()).unwrap();
+        assert_eq!(merged.overridden("a.b"), [0, 1]);
+        // `a.c` is a mapping in the second layer, which is not a leaf.
+        assert_eq!(merged.overridden("a.c"), []);
+        assert_eq!(merged.overridden("d"), [0]);
+        assert_eq!(merged.overridden("d.0"), []);
+
+        let leaves: Vec<(String, Value, usize)> = merged
+            .leaves()
+            .map(|(path, value, layer)| (path.to_string(), value.clone(), layer))
+            .collect();
+        assert_eq!(
+            leaves,
+            vec![
+                (String::from("a.b"), Value::Number(3.into()), 2),
+                (String::from("a.c"), Value::Number(3.into()), 2),
+                (String::from("d"), from_str("[y]").unwrap(), 1),
+            ]
+        );
+    }
+}
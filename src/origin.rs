@@ -0,0 +1,772 @@
+//! This module provides [`Document`] to keep track of where the values of a
+//! YAML document are written, and flattening and unflattening functions that
+//! carry the [`Origin`] of each leaf, e.g., `node.yml:12:3`.
+//!
+//! As [`Value`] does not keep the positions of the parsed nodes, documents are
+//! parsed a second time with the same YAML parser as
+//! [`serde_yaml_ng::from_str()`] to find them. See also
+//! [`merge_documents()`](crate::merge::merge_documents).
+
+use crate::conversion::key_to_token;
+use crate::conversion::parse_key;
+use crate::conversion::try_flatten_keyed_with;
+use crate::conversion::try_flatten_ref_paths_with;
+use crate::conversion::unflatten_with;
+use crate::conversion::ConflictPolicy;
+use crate::conversion::Error;
+use crate::conversion::FlattenOptions;
+use crate::conversion::Segment;
+use crate::conversion::UnflattenOptions;
+use crate::path::get_path;
+use crate::path::Path;
+use crate::path::PathSegment;
+use serde_yaml_ng::Value;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+
+const DOT: &str = ".";
+
+/// Where a value is written, the line and column numbers start from 1.
+///
+/// It is displayed as `document:line:column`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Origin {
+    document: String,
+    line: usize,
+    column: usize,
+}
+
+impl Origin {
+    pub fn new(document: impl Into<String>, line: usize, column: usize) -> Self {
+        Self {
+            document: document.into(),
+            line,
+            column,
+        }
+    }
+
+    /// Returns the name of the document, see [`Document::parse()`].
+    pub fn document(&self) -> &str {
+        &self.document
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.document, self.line, self.column)
+    }
+}
+
+/// A parsed YAML document that remembers where its values are written.
+///
+/// A mapping value is located at its key, and a sequence element at the
+/// element itself. Values without a position of their own, e.g., those
+/// copied by an alias, are located at the innermost value containing them.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_nested::origin::Document;
+/// # use serde_yaml_nested::origin::Origin;
+/// let source = "
+/// cluster:
+///   name: es
+///   hosts: [a, b]
+/// ";
+/// let document = Document::parse("cluster.yml", source).unwrap();
+///
+/// assert_eq!(
+///     document.origin("cluster.name"),
+///     Some(Origin::new("cluster.yml", 3, 3))
+/// );
+/// assert_eq!(
+///     document.origin("cluster.hosts.1").unwrap().to_string(),
+///     "cluster.yml:4:14"
+/// );
+/// assert_eq!(document.origin("cluster.port"), None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    value: Value,
+    /// The positions by the paths of the values, the keys are kept as they
+    /// are.
+    locations: Locations,
+    /// The positions by the paths of the values once the dotted keys are
+    /// split, see [`normalize_nested()`](crate::conversion::normalize_nested).
+    normalized: Locations,
+}
+
+impl Document {
+    /// Parses `source` like [`serde_yaml_ng::from_str()`] does, `name`
+    /// identifies the document in the [`Origin`]s, e.g., its file name.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of [`serde_yaml_ng::from_str()`] if `source` is not
+    /// a valid YAML document.
+    pub fn parse(name: impl Into<String>, source: &str) -> Result<Self, serde_yaml_ng::Error> {
+        let value = serde_yaml_ng::from_str(source)?;
+        let name = name.into();
+        let (marks, normalized_marks) = locate(source);
+
+        Ok(Self {
+            value,
+            locations: Locations {
+                document: name.clone(),
+                marks,
+            },
+            normalized: Locations {
+                document: name,
+                marks: normalized_marks,
+            },
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.locations.document
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+
+    /// Returns where the value at `path` is written, or `None` if there is
+    /// no such value, see [`get_path()`] for how `path` is matched.
+    pub fn origin(&self, path: impl Into<Path>) -> Option<Origin> {
+        let mut value = &self.value;
+        let mut located = Path::new();
+        for segment in path.into() {
+            // Index segments also match mapping keys, which are located as keys.
+            let segment = match (segment, value) {
+                (PathSegment::Index(index), Value::Mapping(_)) => {
+                    PathSegment::Key(index.to_string())
+                }
+                (segment, _) => segment,
+            };
+            value = get_path(value, &Path::from(vec![segment.clone()]))?;
+            located.push(segment);
+        }
+
+        self.locations.origin(&located)
+    }
+
+    /// Splits this document into its value and the positions by the
+    /// normalized paths.
+    pub(crate) fn into_normalized(self) -> (Value, Locations) {
+        (self.value, self.normalized)
+    }
+}
+
+/// The positions of the values of a document by their paths.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Locations {
+    document: String,
+    marks: BTreeMap<Path, Mark>,
+}
+
+impl Locations {
+    /// Returns the origin of `path`, or of the innermost located path
+    /// containing it.
+    pub(crate) fn origin(&self, path: &Path) -> Option<Origin> {
+        let mut path = path.clone();
+        loop {
+            if let Some(mark) = self.marks.get(&path) {
+                return Some(Origin::new(self.document.clone(), mark.line, mark.column));
+            }
+            path.pop()?;
+        }
+    }
+}
+
+/// A position in a document, starting from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mark {
+    line: usize,
+    column: usize,
+}
+
+/// Flattens the value of `document` like
+/// [`try_flatten_ordered_with()`](crate::conversion::try_flatten_ordered_with),
+/// each leaf comes with where it is written.
+///
+/// # Errors
+///
+/// Returns the same errors as
+/// [`try_flatten_ordered_with()`](crate::conversion::try_flatten_ordered_with).
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::FlattenOptions;
+/// # use serde_yaml_nested::origin::try_flatten_origins_with;
+/// # use serde_yaml_nested::origin::Document;
+/// # use serde_yaml_nested::origin::Origin;
+/// let document = Document::parse("node.yml", "node:\n  name: node-1").unwrap();
+///
+/// let flattened = try_flatten_origins_with(&document, &FlattenOptions::new()).unwrap();
+/// assert_eq!(
+///     flattened,
+///     vec![(
+///         String::from("node.name"),
+///         Value::String("node-1".into()),
+///         Origin::new("node.yml", 2, 3),
+///     )]
+/// );
+/// ```
+pub fn try_flatten_origins_with(
+    document: &Document,
+    options: &FlattenOptions,
+) -> Result<Vec<(String, Value, Origin)>, Error> {
+    let flattened = try_flatten_keyed_with(document.value.clone(), options)?;
+
+    Ok(flattened
+        .into_iter()
+        .map(|(key, path, value)| {
+            let origin = document.locations.origin(&path);
+            (key, value, origin.expect("the root is always located"))
+        })
+        .collect())
+}
+
+/// Unflattens the given `input` YAML like [`unflatten_with()`], and returns
+/// the origins of the leaves of the result by their paths.
+///
+/// A leaf comes from the key that sets it, or sets a mapping or sequence
+/// value containing it. If several keys do so, it is the last one with
+/// [`ConflictPolicy::LastWins`], otherwise the first one. Empty mappings and
+/// sequences are not leaves.
+///
+/// # Errors
+///
+/// Returns the same errors as [`unflatten_with()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::ConflictPolicy;
+/// # use serde_yaml_nested::conversion::UnflattenOptions;
+/// # use serde_yaml_nested::origin::try_flatten_origins_with;
+/// # use serde_yaml_nested::origin::unflatten_origins_with;
+/// # use serde_yaml_nested::origin::Document;
+/// let defaults = Document::parse("defaults.yml", "cluster: {name: es, port: 9200}").unwrap();
+/// let node = Document::parse("node.yml", "cluster.name: prod").unwrap();
+///
+/// let mut flattened = Vec::new();
+/// for document in [&defaults, &node] {
+///     flattened.extend(try_flatten_origins_with(document, &Default::default()).unwrap());
+/// }
+/// let options = UnflattenOptions::new().on_conflict(ConflictPolicy::LastWins);
+/// let (nested, origins) = unflatten_origins_with(flattened, &options).unwrap();
+///
+/// assert_eq!(
+///     nested,
+///     from_str::<Value>("cluster: {name: prod, port: 9200}").unwrap()
+/// );
+/// assert_eq!(origins[&"cluster.name".into()].to_string(), "node.yml:1:1");
+/// assert_eq!(
+///     origins[&"cluster.port".into()].to_string(),
+///     "defaults.yml:1:21"
+/// );
+/// ```
+pub fn unflatten_origins_with<I: IntoIterator<Item = (String, Value, Origin)>>(
+    input: I,
+    options: &UnflattenOptions,
+) -> Result<(Value, BTreeMap<Path, Origin>), Error> {
+    let mut entries = Vec::new();
+    let mut values = Vec::new();
+    let mut origins = Vec::new();
+    let mut setters: HashMap<Path, Vec<usize>> = HashMap::new();
+    for (nth, (key, value, origin)) in input.into_iter().enumerate() {
+        let path: Path = parse_key(&key, options)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Key(Value::String(key)) => PathSegment::Key(key),
+                Segment::Key(key) => PathSegment::Key(Segment::Key(key).to_string()),
+                Segment::Index(index) => PathSegment::Index(index),
+            })
+            .collect();
+        setters.entry(path).or_default().push(nth);
+        values.push(value.clone());
+        entries.push((key, value));
+        origins.push(origin);
+    }
+
+    let nested = unflatten_with(entries, options)?;
+    let leaves =
+        try_flatten_ref_paths_with(&nested, &FlattenOptions::new().flatten_sequences(true))?;
+    let values = &values;
+    let mut leaf_origins = BTreeMap::new();
+    for (leaf, _) in leaves {
+        // The keys at the leaf or above it, whose values contain the leaf.
+        let candidates = (0..=leaf.len()).flat_map(|len| {
+            let (prefix, rest) = leaf.segments().split_at(len);
+            let rest = Path::from(rest.to_vec());
+            setters
+                .get(&Path::from(prefix.to_vec()))
+                .into_iter()
+                .flatten()
+                .filter(move |&&nth| get_path(&values[nth], &rest).is_some())
+        });
+        let setter = match options.conflict_policy() {
+            ConflictPolicy::LastWins => candidates.max(),
+            _ => candidates.min(),
+        };
+        if let Some(setter) = setter {
+            leaf_origins.insert(leaf, origins[*setter].clone());
+        }
+    }
+
+    Ok((nested, leaf_origins))
+}
+
+/// Finds the positions of the values in `source` by their paths, both as
+/// they are and once the dotted keys are split.
+///
+/// Only the first document is walked, and the walk stops quietly on a
+/// syntax error, which [`serde_yaml_ng::from_str()`] reports.
+fn locate(source: &str) -> (BTreeMap<Path, Mark>, BTreeMap<Path, Mark>) {
+    let mut marks = BTreeMap::new();
+    let mut normalized_marks = BTreeMap::new();
+    let mut parser = Parser::new(source);
+    let mut stack: Vec<Frame> = Vec::new();
+    // The depth of the key or value being skipped, as it cannot be located.
+    let mut skipped = 0;
+    while let Some((event, mark)) = parser.next() {
+        let container = match event {
+            Event::MappingStart => Some(Kind::Mapping(None)),
+            Event::SequenceStart => Some(Kind::Sequence(0)),
+            _ => None,
+        };
+        if skipped > 0 {
+            match event {
+                Event::MappingStart | Event::SequenceStart => skipped += 1,
+                Event::End => skipped -= 1,
+                _ => {}
+            }
+            continue;
+        }
+
+        let (path, normalized, mark) = match event {
+            Event::DocumentEnd => break,
+            Event::Other => continue,
+            Event::End => {
+                stack.pop();
+                continue;
+            }
+            node => match stack.last_mut() {
+                None => (Path::new(), Path::new(), mark),
+                Some(Frame {
+                    kind: Kind::Mapping(key @ None),
+                    ..
+                }) => {
+                    *key = Some(match node {
+                        Event::Scalar(scalar) if !scalar.tagged => {
+                            locate_key(scalar).map(|(segment, segments)| (segment, segments, mark))
+                        }
+                        _ => None,
+                    });
+                    if container.is_some() {
+                        skipped = 1;
+                    }
+                    continue;
+                }
+                Some(Frame {
+                    path,
+                    normalized,
+                    kind: Kind::Mapping(key),
+                }) => match key.take().flatten() {
+                    Some((segment, segments, key_mark)) => {
+                        let mut path = path.clone();
+                        path.push(segment);
+                        let mut normalized = normalized.clone();
+                        for segment in segments {
+                            normalized.push(segment);
+                        }
+                        (path, normalized, key_mark)
+                    }
+                    None => {
+                        if container.is_some() {
+                            skipped = 1;
+                        }
+                        continue;
+                    }
+                },
+                Some(Frame {
+                    path,
+                    normalized,
+                    kind: Kind::Sequence(index),
+                }) => {
+                    let mut path = path.clone();
+                    path.push(PathSegment::Index(*index));
+                    let mut normalized = normalized.clone();
+                    normalized.push(PathSegment::Index(*index));
+                    *index += 1;
+                    (path, normalized, mark)
+                }
+            },
+        };
+
+        marks.entry(path.clone()).or_insert(mark);
+        normalized_marks.entry(normalized.clone()).or_insert(mark);
+        if let Some(kind) = container {
+            stack.push(Frame {
+                path,
+                normalized,
+                kind,
+            });
+        }
+    }
+
+    (marks, normalized_marks)
+}
+
+/// A mapping or sequence being walked by [`locate()`].
+struct Frame {
+    path: Path,
+    normalized: Path,
+    kind: Kind,
+}
+
+enum Kind {
+    /// `None` if the next node is a key, otherwise the key of the next
+    /// value, which is `None` if it cannot be located.
+    Mapping(Option<Option<LocatedKey>>),
+    /// The index of the next element.
+    Sequence(usize),
+}
+
+/// The segment of a mapping key, the segments once it is split, and where it
+/// is written.
+type LocatedKey = (PathSegment, Vec<PathSegment>, Mark);
+
+/// Resolves a mapping key the same way as [`serde_yaml_ng::from_str()`]
+/// does, returns its segment and the segments once it is split, or `None` if
+/// it cannot be flattened.
+fn locate_key(scalar: Scalar) -> Option<(PathSegment, Vec<PathSegment>)> {
+    let key = match serde_yaml_ng::from_str(&scalar.value) {
+        Ok(key @ (Value::Null | Value::Bool(_) | Value::Number(_))) if scalar.plain => key,
+        _ => Value::String(scalar.value),
+    };
+    let token = key_to_token(key.clone(), String::new).ok()?;
+    let normalized = match key {
+        Value::String(key) => key.split(DOT).map(PathSegment::from).collect(),
+        _ => vec![PathSegment::Key(token.clone())],
+    };
+
+    Some((PathSegment::Key(token), normalized))
+}
+
+/// The events of [`Parser`] that matter to [`locate()`].
+enum Event {
+    Scalar(Scalar),
+    Alias,
+    MappingStart,
+    SequenceStart,
+    /// The end of a mapping or a sequence.
+    End,
+    DocumentEnd,
+    /// The start of the stream or the document.
+    Other,
+}
+
+struct Scalar {
+    value: String,
+    plain: bool,
+    tagged: bool,
+}
+
+/// A minimal wrapper of the libyaml parser, which is also the parser of
+/// [`serde_yaml_ng`], so the positions match the parsed values. Its version
+/// is pinned to the one `serde_yaml_ng` uses.
+struct Parser<'input> {
+    sys: Box<MaybeUninit<unsafe_libyaml::yaml_parser_t>>,
+    /// The parser reads the input through a pointer.
+    input: PhantomData<&'input str>,
+}
+
+impl<'input> Parser<'input> {
+    fn new(input: &'input str) -> Self {
+        let mut sys = Box::new(MaybeUninit::<unsafe_libyaml::yaml_parser_t>::uninit());
+        // SAFETY: the parser is initialized before use and deleted on drop,
+        // and the boxed parser never moves. `input` outlives the parser.
+        unsafe {
+            let parser = sys.as_mut_ptr();
+            if unsafe_libyaml::yaml_parser_initialize(parser).fail {
+                panic!("failed to initialize the YAML parser");
+            }
+            unsafe_libyaml::yaml_parser_set_encoding(parser, unsafe_libyaml::YAML_UTF8_ENCODING);
+            unsafe_libyaml::yaml_parser_set_input_string(
+                parser,
+                input.as_ptr(),
+                input.len() as u64,
+            );
+        }
+
+        Self {
+            sys,
+            input: PhantomData,
+        }
+    }
+
+    /// Returns the next event and where it starts, or `None` at the end of
+    /// the stream or on a syntax error.
+    fn next(&mut self) -> Option<(Event, Mark)> {
+        let mut event = MaybeUninit::<unsafe_libyaml::yaml_event_t>::uninit();
+        // SAFETY: the parser is initialized, and the event is read only if
+        // the parser fills it, then deleted.
+        unsafe {
+            let parser = self.sys.as_mut_ptr();
+            if (*parser).error != unsafe_libyaml::YAML_NO_ERROR {
+                return None;
+            }
+            let event = event.as_mut_ptr();
+            if unsafe_libyaml::yaml_parser_parse(parser, event).fail {
+                return None;
+            }
+
+            let converted = match (*event).type_ {
+                unsafe_libyaml::YAML_STREAM_END_EVENT => None,
+                unsafe_libyaml::YAML_SCALAR_EVENT => {
+                    let scalar = (*event).data.scalar;
+                    let value = std::slice::from_raw_parts(scalar.value, scalar.length as usize);
+                    Some(Event::Scalar(Scalar {
+                        value: String::from_utf8_lossy(value).into_owned(),
+                        plain: scalar.style == unsafe_libyaml::YAML_PLAIN_SCALAR_STYLE,
+                        tagged: !scalar.tag.is_null(),
+                    }))
+                }
+                unsafe_libyaml::YAML_ALIAS_EVENT => Some(Event::Alias),
+                unsafe_libyaml::YAML_MAPPING_START_EVENT => Some(Event::MappingStart),
+                unsafe_libyaml::YAML_SEQUENCE_START_EVENT => Some(Event::SequenceStart),
+                unsafe_libyaml::YAML_MAPPING_END_EVENT
+                | unsafe_libyaml::YAML_SEQUENCE_END_EVENT => Some(Event::End),
+                unsafe_libyaml::YAML_DOCUMENT_END_EVENT => Some(Event::DocumentEnd),
+                _ => Some(Event::Other),
+            };
+            let mark = Mark {
+                line: (*event).start_mark.line as usize + 1,
+                column: (*event).start_mark.column as usize + 1,
+            };
+            unsafe_libyaml::yaml_event_delete(event);
+
+            converted.map(|event| (event, mark))
+        }
+    }
+}
+
+impl Drop for Parser<'_> {
+    fn drop(&mut self) {
+        // SAFETY: the parser is initialized in `new()`.
+        unsafe { unsafe_libyaml::yaml_parser_delete(self.sys.as_mut_ptr()) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_origins_match_serde_yaml_ng() {
+        // Each document fails to deserialize at the key that is not a number,
+        // whose position serde_yaml_ng reports with its own parser.
+        fn location<T>(result: Result<T, serde_yaml_ng::Error>) -> (usize, usize) {
+            let location = result.err().and_then(|error| error.location()).unwrap();
+            (location.line(), location.column())
+        }
+
+        let flat = "1: a\n\n'x':   b";
+        let flow = "{1: é, 2: {ü: c}, \"ü\": d}";
+        let block = "- {1: a}\n-   2: b\n    x: c";
+        let cases = [
+            (flat, "x", location(from_str::<BTreeMap<u8, String>>(flat))),
+            (flow, "ü", location(from_str::<BTreeMap<u8, Value>>(flow))),
+            (
+                block,
+                "1.x",
+                location(from_str::<Vec<BTreeMap<u8, String>>>(block)),
+            ),
+        ];
+
+        for (source, path, (line, column)) in cases {
+            let document = Document::parse("test.yml", source).unwrap();
+            assert_eq!(
+                document.origin(path),
+                Some(Origin::new("test.yml", line, column)),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_document_origins() {
+        let source = r#"
+cluster.name: es
+cluster:
+  routing: {allocation.enable: all}
+  hosts:
+    - a
+    - {b: c}
+1: one
+"2": two
+anchored: &anchor {x: y}
+alias: *anchor
+? [complex]
+: {d: e}
+!tagged f: {g: h}
+tagged: !tag {i: j}
+"#;
+        let document = Document::parse("cluster.yml", source).unwrap();
+        assert_eq!(document.name(), "cluster.yml");
+        assert_eq!(document.value(), &from_str::<Value>(source).unwrap());
+
+        let origin = |path: &str| document.origin(path).map(|origin| origin.to_string());
+        assert_eq!(origin(""), Some(String::from("cluster.yml:2:1")));
+        assert_eq!(
+            origin(r"cluster\.name"),
+            Some(String::from("cluster.yml:2:1"))
+        );
+        assert_eq!(origin("cluster.name"), None);
+        assert_eq!(
+            origin(r"cluster.routing.allocation\.enable"),
+            Some(String::from("cluster.yml:4:13"))
+        );
+        assert_eq!(
+            origin("cluster.hosts"),
+            Some(String::from("cluster.yml:5:3"))
+        );
+        assert_eq!(
+            origin("cluster.hosts.0"),
+            Some(String::from("cluster.yml:6:7"))
+        );
+        assert_eq!(
+            origin("cluster.hosts.1.b"),
+            Some(String::from("cluster.yml:7:8"))
+        );
+        assert_eq!(origin("1"), Some(String::from("cluster.yml:8:1")));
+        assert_eq!(origin(r"\2"), Some(String::from("cluster.yml:9:1")));
+        assert_eq!(
+            origin("anchored.x"),
+            Some(String::from("cluster.yml:10:20"))
+        );
+        // The values of aliases are located as a whole, tagged values are
+        // never looked into.
+        assert_eq!(origin("alias.x"), Some(String::from("cluster.yml:11:1")));
+        assert_eq!(origin("tagged"), Some(String::from("cluster.yml:15:1")));
+        assert_eq!(origin("tagged.i"), None);
+
+        // Nodes after keys that cannot be flattened are still located.
+        let document = Document::parse("x.yml", "{[a]: {b: c}, d: e}").unwrap();
+        assert_eq!(document.origin("d"), Some(Origin::new("x.yml", 1, 15)));
+
+        assert!(Document::parse("x.yml", "a: [b").is_err());
+        assert!(Document::parse("x.yml", "{a: 1, a: 2}").is_err());
+    }
+
+    #[test]
+    fn test_flatten_origins() {
+        let source = "
+cluster:
+  name: es
+  hosts: [a, b]
+node.roles: [master]
+";
+        let document = Document::parse("cluster.yml", source).unwrap();
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let flattened: Vec<(String, String)> = try_flatten_origins_with(&document, &options)
+            .unwrap()
+            .into_iter()
+            .map(|(key, _, origin)| (key, origin.to_string()))
+            .collect();
+        assert_eq!(
+            flattened,
+            vec![
+                (
+                    String::from("cluster.name"),
+                    String::from("cluster.yml:3:3")
+                ),
+                (
+                    String::from("cluster.hosts.0"),
+                    String::from("cluster.yml:4:11")
+                ),
+                (
+                    String::from("cluster.hosts.1"),
+                    String::from("cluster.yml:4:14")
+                ),
+                (
+                    String::from("node.roles.0"),
+                    String::from("cluster.yml:5:14")
+                ),
+            ]
+        );
+
+        let document = Document::parse("x.yml", "{[a]: b}").unwrap();
+        assert!(try_flatten_origins_with(&document, &options).is_err());
+    }
+
+    #[test]
+    fn test_unflatten_origins() {
+        let origin = |line| Origin::new("x.yml", line, 1);
+        let input = || {
+            vec![
+                (String::from("a.b"), Value::Null, origin(1)),
+                (String::from("a.b.c"), Value::Null, origin(2)),
+                (String::from("d"), from_str("{e: [f]}").unwrap(), origin(3)),
+                (String::from("d.g"), Value::Null, origin(4)),
+                (String::from("s.0"), Value::Null, origin(5)),
+            ]
+        };
+        let origins = |options: &UnflattenOptions| {
+            let (_, origins) = unflatten_origins_with(input(), options).unwrap();
+            origins
+                .into_iter()
+                .map(|(path, origin)| (path.to_string(), origin.line()))
+                .collect::<Vec<_>>()
+        };
+
+        let options = UnflattenOptions::new()
+            .sequences(true)
+            .on_conflict(ConflictPolicy::LastWins);
+        assert_eq!(
+            origins(&options),
+            vec![
+                (String::from("a.b.c"), 2),
+                (String::from("d.e.0"), 3),
+                (String::from("d.g"), 4),
+                (String::from("s.0"), 5),
+            ]
+        );
+
+        let options = options.on_conflict(ConflictPolicy::FirstWins);
+        assert_eq!(
+            origins(&options),
+            vec![
+                (String::from("a.b"), 1),
+                (String::from("d.e.0"), 3),
+                (String::from("d.g"), 4),
+                (String::from("s.0"), 5),
+            ]
+        );
+
+        assert!(unflatten_origins_with(input(), &UnflattenOptions::new()).is_err());
+    }
+}
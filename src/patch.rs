@@ -0,0 +1,376 @@
+//! This module provides [`Patch`], a list of changes keyed by [`Path`]s,
+//! which can be applied to a YAML value with [`apply()`] and rolled back with
+//! [`Patch::invert()`].
+
+use crate::conversion::Error;
+use crate::diff::Change;
+use crate::path::get_path_mut;
+use crate::path::remove_child;
+use crate::path::set_path;
+use crate::path::Path;
+use crate::path::PathSegment;
+use serde_yaml_ng::Value;
+
+/// An operation of a [`Patch`].
+///
+/// The values being replaced or removed are kept so that the operation can
+/// be inverted.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    /// Sets `path` to `value`, `old` is the value it replaces, or `None` if
+    /// `path` is added. An added sequence element is inserted before the
+    /// element at its index, like the JSON Patch `add` operation.
+    Set {
+        path: Path,
+        value: Value,
+        old: Option<Value>,
+    },
+    /// Removes `old`, the value at `path`.
+    Remove { path: Path, old: Value },
+}
+
+impl Operation {
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Set { path, .. } | Self::Remove { path, .. } => path,
+        }
+    }
+
+    /// Returns the operation that undoes this one.
+    pub fn invert(&self) -> Operation {
+        match self.clone() {
+            Self::Set {
+                path,
+                value,
+                old: Some(old),
+            } => Self::Set {
+                path,
+                value: old,
+                old: Some(value),
+            },
+            Self::Set {
+                path,
+                value,
+                old: None,
+            } => Self::Remove { path, old: value },
+            Self::Remove { path, old } => Self::Set {
+                path,
+                value: old,
+                old: None,
+            },
+        }
+    }
+}
+
+impl From<Change> for Operation {
+    fn from(change: Change) -> Self {
+        match change {
+            Change::Added { path, value } => Self::Set {
+                path,
+                value,
+                old: None,
+            },
+            Change::Removed { path, value } => Self::Remove { path, old: value },
+            Change::Modified { path, old, new } => Self::Set {
+                path,
+                value: new,
+                old: Some(old),
+            },
+        }
+    }
+}
+
+/// A list of [`Operation`]s that are applied in order.
+///
+/// A patch can be built from the changes found by
+/// [`diff()`](crate::diff::diff), so that applying it to the old document
+/// gives the new one. The removals come first, from the last path to the
+/// first so that removing sequence elements doesn't shift the elements still
+/// to be removed, followed by the other operations in the order of their
+/// paths.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::diff::diff;
+/// # use serde_yaml_nested::patch::apply;
+/// # use serde_yaml_nested::patch::Patch;
+/// let old: Value = from_str("cluster: {name: es, routing: {enable: all}}").unwrap();
+/// let new: Value = from_str("cluster: {name: prod, nodes: 3}").unwrap();
+///
+/// let patch = Patch::from(diff(&old, &new));
+/// let mut value = old.clone();
+/// apply(&mut value, &patch).unwrap();
+/// assert_eq!(value, new);
+///
+/// apply(&mut value, &patch.invert()).unwrap();
+/// assert_eq!(value, old);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Patch {
+    operations: Vec<Operation>,
+}
+
+impl Patch {
+    /// Creates an empty patch.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    pub fn push(&mut self, operation: Operation) {
+        self.operations.push(operation);
+    }
+
+    /// Returns the patch that undoes this one, i.e., the inverted
+    /// operations in the reverse order.
+    pub fn invert(&self) -> Patch {
+        self.operations
+            .iter()
+            .rev()
+            .map(Operation::invert)
+            .collect()
+    }
+}
+
+impl From<Vec<Change>> for Patch {
+    fn from(changes: Vec<Change>) -> Self {
+        let (mut removals, mut others): (Vec<Operation>, Vec<Operation>) = changes
+            .into_iter()
+            .map(Operation::from)
+            .partition(|operation| matches!(operation, Operation::Remove { .. }));
+        removals.sort_by(|left, right| right.path().cmp(left.path()));
+        others.sort_by(|left, right| left.path().cmp(right.path()));
+
+        removals.into_iter().chain(others).collect()
+    }
+}
+
+impl FromIterator<Operation> for Patch {
+    fn from_iter<I: IntoIterator<Item = Operation>>(iter: I) -> Self {
+        Self {
+            operations: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Applies `patch` to `value`.
+///
+/// Missing parents are created as [`set_path()`] does, while a removal only
+/// removes the value at its path, so that the parents emptied by it are kept.
+/// The elements after a removed or an added sequence element are shifted.
+/// The `old` values of the operations are not checked, except that `None`
+/// tells an added sequence element from a replaced one.
+///
+/// # Errors
+///
+/// Returns the errors of [`set_path()`] if a value cannot be set, and
+/// [`Error::PathNotFound`] if there is nothing to remove. `value` is not
+/// changed if any operation fails.
+pub fn apply(value: &mut Value, patch: &Patch) -> Result<(), Error> {
+    let mut patched = value.clone();
+    for operation in &patch.operations {
+        match operation {
+            Operation::Set {
+                path,
+                value,
+                old: None,
+            } => insert(&mut patched, path, value.clone())?,
+            Operation::Set { path, value, .. } => {
+                set_path(&mut patched, path, value.clone())?;
+            }
+            Operation::Remove { path, .. } => {
+                let removed = path.parent().and_then(|parent| {
+                    let last = path.segments().last().expect("the path has a parent");
+                    remove_child(get_path_mut(&mut patched, &parent)?, last)
+                });
+                if removed.is_none() {
+                    return Err(Error::PathNotFound {
+                        path: path.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    *value = patched;
+    Ok(())
+}
+
+/// Inserts `new_value` at `path` in `value` if it is an index of a sequence,
+/// so that the element there and the ones after it are shifted, otherwise
+/// sets it like [`set_path()`] does.
+fn insert(value: &mut Value, path: &Path, new_value: Value) -> Result<(), Error> {
+    if let (Some(parent), Some(PathSegment::Index(index))) = (path.parent(), path.segments().last())
+    {
+        if let Some(Value::Sequence(sequence)) = get_path_mut(value, &parent) {
+            if *index < sequence.len() {
+                sequence.insert(*index, new_value);
+                return Ok(());
+            }
+        }
+    }
+
+    set_path(value, path, new_value)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversion::FlattenOptions;
+    use crate::diff::diff;
+    use crate::diff::try_diff_with;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_apply_and_invert() {
+        let old: Value = from_str("{a: {b: [x, y], c: 1}, d: {e: 2}}").unwrap();
+        let new: Value = from_str("{a: {b: [x, z, w], f: {g: 3}}, h: null}").unwrap();
+
+        let options = FlattenOptions::new().flatten_sequences(true);
+        let patch = Patch::from(try_diff_with(&old, &new, &options).unwrap());
+        assert_eq!(
+            patch.operations()[..3],
+            [
+                Operation::Remove {
+                    path: Path::from("d"),
+                    old: from_str("{e: 2}").unwrap(),
+                },
+                Operation::Remove {
+                    path: Path::from("a.c"),
+                    old: Value::Number(1.into()),
+                },
+                Operation::Set {
+                    path: Path::from("a.b.1"),
+                    value: Value::String("z".into()),
+                    old: Some(Value::String("y".into())),
+                },
+            ]
+        );
+
+        let mut value = old.clone();
+        apply(&mut value, &patch).unwrap();
+        assert_eq!(value, new);
+
+        apply(&mut value, &patch.invert()).unwrap();
+        assert_eq!(value, old);
+        assert_eq!(patch.invert().invert(), patch);
+    }
+
+    #[test]
+    fn test_apply_round_trip() {
+        let documents = [
+            ("{a: [x, y, z]}", "{a: [x]}"),
+            ("{a: [x, {c: 1}, z]}", "{a: [x, {d: 2}, z]}"),
+            ("{a: [x, {c: 1}]}", "{a: [x]}"),
+            ("{a: {b: 1}}", "{a: {}}"),
+            ("{a: {b: 1}, c: 2}", "{c: 2}"),
+            ("{a: 1, b: []}", "{a: {b: [1, 2]}, b: [x]}"),
+            ("{a.b: 1, a: {c: 2}}", "{a.b: 2, a: {b: 3}}"),
+            (
+                r"{'C:\dir': {temp: x}}",
+                r"{'C:\dir': {temp: y}, 'a\.b': z}",
+            ),
+            ("x", "{a: 1}"),
+            ("x", "y"),
+            ("[x]", "{}"),
+            ("{a: {}, b: 1}", "{b: 1}"),
+            ("{a: {}}", "{a: {c: 1}}"),
+            ("{a: [{}, x]}", "{a: [{b: {}}]}"),
+        ];
+
+        for (old, new) in documents {
+            let old: Value = from_str(old).unwrap();
+            let new: Value = from_str(new).unwrap();
+
+            for options in [
+                FlattenOptions::new(),
+                FlattenOptions::new().flatten_sequences(true),
+            ] {
+                let patch = Patch::from(try_diff_with(&old, &new, &options).unwrap());
+                let mut value = old.clone();
+                apply(&mut value, &patch).unwrap();
+                assert_eq!(value, new, "{:?}", patch);
+
+                apply(&mut value, &patch.invert()).unwrap();
+                assert_eq!(value, old, "{:?}", patch);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invert_sequence_removal() {
+        let old: Value = from_str("a: [x, y, z]").unwrap();
+        let patch = Patch::from_iter([Operation::Remove {
+            path: Path::from("a.1"),
+            old: Value::String("y".into()),
+        }]);
+
+        let mut value = old.clone();
+        apply(&mut value, &patch).unwrap();
+        assert_eq!(value, from_str::<Value>("a: [x, z]").unwrap());
+
+        apply(&mut value, &patch.invert()).unwrap();
+        assert_eq!(value, old);
+
+        let patch = Patch::from_iter([Operation::Set {
+            path: Path::from("a.0"),
+            value: Value::String("w".into()),
+            old: Some(Value::String("x".into())),
+        }]);
+        apply(&mut value, &patch).unwrap();
+        assert_eq!(value, from_str::<Value>("a: [w, y, z]").unwrap());
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let mut value: Value = from_str("{a: 1, b: [x]}").unwrap();
+        let original = value.clone();
+
+        let patch = Patch::from_iter([
+            Operation::Set {
+                path: Path::from("c"),
+                value: Value::Null,
+                old: None,
+            },
+            Operation::Set {
+                path: Path::from("a.b"),
+                value: Value::Null,
+                old: None,
+            },
+        ]);
+        assert_eq!(
+            apply(&mut value, &patch),
+            Err(Error::DuplicateValue {
+                key: String::from("a.b"),
+                token: String::from("a"),
+                prior: String::from("a"),
+            })
+        );
+        assert_eq!(value, original);
+
+        for path in ["b.1", ""] {
+            let patch = Patch::from_iter([Operation::Remove {
+                path: Path::from(path),
+                old: Value::Null,
+            }]);
+            assert_eq!(
+                apply(&mut value, &patch),
+                Err(Error::PathNotFound {
+                    path: String::from(path),
+                })
+            );
+        }
+
+        let patch = Patch::from(diff(&value, &from_str("{a: 1, b: []}").unwrap()));
+        apply(&mut value, &patch).unwrap();
+        assert_eq!(value, from_str::<Value>("{a: 1, b: []}").unwrap());
+    }
+}
@@ -0,0 +1,811 @@
+//! This module provides [`Path`], a path into a nested YAML value made of
+//! [`PathSegment`]s, so that the segment boundaries are never lost by joining
+//! and splitting strings, and functions like [`get_path()`] and [`set_path()`]
+//! to access a nested value by path.
+
+use crate::conversion::escape_key;
+use crate::conversion::infer_key_type;
+use crate::conversion::parse_index;
+use crate::conversion::parse_key;
+use crate::conversion::Error;
+use crate::conversion::Segment;
+use crate::conversion::SequenceErrorKind;
+use crate::conversion::UnflattenOptions;
+use serde_yaml_ng::Mapping;
+use serde_yaml_ng::Value;
+use std::convert::Infallible;
+use std::str::FromStr;
+
+const DOT: &str = ".";
+/// How a path made of a single empty key is displayed, to tell it from the
+/// empty path. The backslash escapes nothing, see
+/// [`UnflattenOptions::escaping()`].
+const EMPTY_KEY: &str = "\\";
+
+/// A segment of a [`Path`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PathSegment {
+    /// A mapping key, boolean and number keys are written as strings.
+    Key(String),
+    /// A sequence index.
+    Index(usize),
+}
+
+impl std::fmt::Display for PathSegment {
+    /// Writes the segment as a token of the canonical form, see
+    /// [`Path::parse()`].
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Key(key) => {
+                let mark_string = parse_index(key).is_some();
+                write!(f, "{}", escape_key(key, DOT, mark_string))
+            }
+            Self::Index(index) => write!(f, "{}", index),
+        }
+    }
+}
+
+impl From<&str> for PathSegment {
+    fn from(key: &str) -> Self {
+        Self::Key(key.to_string())
+    }
+}
+
+impl From<String> for PathSegment {
+    fn from(key: String) -> Self {
+        Self::Key(key)
+    }
+}
+
+impl From<usize> for PathSegment {
+    fn from(index: usize) -> Self {
+        Self::Index(index)
+    }
+}
+
+/// A path into a nested YAML value, the empty path is the value itself.
+///
+/// Paths are compared segment by segment, so indexes are ordered as numbers,
+/// e.g., `a.2` comes before `a.10`.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_nested::path::Path;
+/// # use serde_yaml_nested::path::PathSegment;
+/// let path = Path::parse(r"cluster.node\.attr.0");
+/// assert_eq!(
+///     path.segments(),
+///     [
+///         PathSegment::Key("cluster".into()),
+///         PathSegment::Key("node.attr".into()),
+///         PathSegment::Index(0),
+///     ]
+/// );
+/// assert_eq!(path.to_string(), r"cluster.node\.attr.0");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Path {
+    segments: Vec<PathSegment>,
+}
+
+impl Path {
+    /// Creates the empty path.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses the canonical form of a path, which is how it is displayed.
+    ///
+    /// The tokens are separated by `.`, and a backslash escapes the
+    /// character following it. Tokens that are sequence indexes (`0`, `1`,
+    /// ..., without leading zeros) become index segments unless they contain
+    /// an escape, e.g., `\0`, other tokens become key segments.
+    ///
+    /// An empty string is parsed as the empty path, and a backslash at the
+    /// end escapes nothing, so a lone backslash is a path made of a single
+    /// empty key.
+    pub fn parse(path: &str) -> Self {
+        let options = UnflattenOptions::new().escaping(true).sequences(true);
+        Self::parse_with(path, &options)
+    }
+
+    /// Parses a flattened key according to the separator, escaping and
+    /// sequences settings in `options`, the same way as
+    /// [`unflatten_with()`](crate::conversion::unflatten_with) does.
+    ///
+    /// An empty string is parsed as the empty path.
+    pub fn parse_with(key: &str, options: &UnflattenOptions) -> Self {
+        if key.is_empty() {
+            return Self::new();
+        }
+
+        parse_key(key, options)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Key(key) => PathSegment::Key(match key {
+                    Value::String(key) => key,
+                    key => Segment::Key(key).to_string(),
+                }),
+                Segment::Index(index) => PathSegment::Index(index),
+            })
+            .collect()
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    /// Appends `segment` to the end of this path.
+    pub fn push(&mut self, segment: impl Into<PathSegment>) {
+        self.segments.push(segment.into());
+    }
+
+    /// Removes the last segment and returns it, or `None` if it is empty.
+    pub fn pop(&mut self) -> Option<PathSegment> {
+        self.segments.pop()
+    }
+
+    /// Returns the path without its last segment, or `None` if it is empty.
+    pub fn parent(&self) -> Option<Path> {
+        let (_, parent) = self.segments.split_last()?;
+        Some(parent.iter().cloned().collect())
+    }
+
+    /// Returns `true` if `prefix` is the leading segments of this path.
+    pub fn starts_with(&self, prefix: &Path) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, PathSegment> {
+        self.segments.iter()
+    }
+}
+
+impl std::fmt::Display for Path {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let [PathSegment::Key(key)] = self.segments.as_slice() {
+            if key.is_empty() {
+                return write!(f, "{}", EMPTY_KEY);
+            }
+        }
+
+        for (nth, segment) in self.segments.iter().enumerate() {
+            if nth > 0 {
+                write!(f, "{}", DOT)?;
+            }
+            write!(f, "{}", segment)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Path {
+    type Err = Infallible;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse(path))
+    }
+}
+
+impl From<&str> for Path {
+    /// Parses `path` in the canonical form, see [`Path::parse()`].
+    fn from(path: &str) -> Self {
+        Self::parse(path)
+    }
+}
+
+impl From<String> for Path {
+    /// Parses `path` in the canonical form, see [`Path::parse()`].
+    fn from(path: String) -> Self {
+        Self::parse(&path)
+    }
+}
+
+impl From<Vec<PathSegment>> for Path {
+    fn from(segments: Vec<PathSegment>) -> Self {
+        Self { segments }
+    }
+}
+
+impl FromIterator<PathSegment> for Path {
+    fn from_iter<I: IntoIterator<Item = PathSegment>>(iter: I) -> Self {
+        Self {
+            segments: iter.into_iter().collect(),
+        }
+    }
+}
+
+impl IntoIterator for Path {
+    type IntoIter = std::vec::IntoIter<PathSegment>;
+    type Item = PathSegment;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Path {
+    type IntoIter = std::slice::Iter<'a, PathSegment>;
+    type Item = &'a PathSegment;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.segments.iter()
+    }
+}
+
+/// Returns the value at `path` in `value`, or `None` if there is no such
+/// value.
+///
+/// Use [`get_path_with()`] to look up a flattened key, which is split the
+/// same way as [`unflatten_with()`](crate::conversion::unflatten_with) does.
+///
+/// A key segment matches a string key, or a boolean or number key that is
+/// written the same way, e.g., `1`. An index segment matches a sequence
+/// element, or a number or string key. Tagged values are never looked into,
+/// the same as [`flatten()`](crate::conversion::flatten).
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::path::get_path;
+/// # use serde_yaml_nested::path::Path;
+/// let value: Value = from_str("cluster: {routing: {allocation: {enable: all}}}").unwrap();
+///
+/// assert_eq!(
+///     get_path(&value, &Path::parse("cluster.routing.allocation.enable")),
+///     Some(&Value::String("all".into()))
+/// );
+/// assert_eq!(get_path(&value, &Path::parse("cluster.name")), None);
+/// ```
+pub fn get_path<'a>(value: &'a Value, path: &Path) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match value {
+        Value::Mapping(mapping) => mapping.get(find_key(mapping, segment)?),
+        Value::Sequence(sequence) => sequence.get(as_index(segment)?),
+        _ => None,
+    })
+}
+
+/// Returns the value at the flattened `key` in `value` like [`get_path()`],
+/// but `key` is parsed according to `options`, see [`Path::parse_with()`].
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::conversion::UnflattenOptions;
+/// # use serde_yaml_nested::path::get_path_with;
+/// let value: Value = from_str(r"{'C:\dir': {temp: x}}").unwrap();
+///
+/// assert_eq!(
+///     get_path_with(&value, r"C:\dir.temp", &UnflattenOptions::new()),
+///     Some(&Value::String("x".into()))
+/// );
+/// ```
+pub fn get_path_with<'a>(
+    value: &'a Value,
+    key: &str,
+    options: &UnflattenOptions,
+) -> Option<&'a Value> {
+    get_path(value, &Path::parse_with(key, options))
+}
+
+/// The mutable version of [`get_path()`].
+pub fn get_path_mut<'a>(value: &'a mut Value, path: &Path) -> Option<&'a mut Value> {
+    get_segments_mut(value, path.segments())
+}
+
+/// The mutable version of [`get_path_with()`].
+pub fn get_path_mut_with<'a>(
+    value: &'a mut Value,
+    key: &str,
+    options: &UnflattenOptions,
+) -> Option<&'a mut Value> {
+    get_path_mut(value, &Path::parse_with(key, options))
+}
+
+/// Sets the value at `path` in `value` to `new_value`, returns the value that
+/// is replaced, if any.
+///
+/// Missing mappings and sequences are created like what
+/// [`unflatten_paths_with()`](crate::conversion::unflatten_paths_with) does,
+/// null values on the way are replaced as if they are missing. An index can
+/// be appended to a sequence, but it cannot go beyond the end.
+///
+/// Segments are matched like [`get_path()`] does, new keys are added as
+/// strings, or numbers for index segments. Use [`set_path_with()`] to set a
+/// flattened key.
+///
+/// # Errors
+///
+/// Returns [`Error::DuplicateValue`] if a value on the way is neither a
+/// mapping, a null or a sequence indexed by an index segment, and
+/// [`Error::InvalidSequence`] if an index is beyond the end of a sequence.
+/// `value` is not changed in these cases.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::path::set_path;
+/// # use serde_yaml_nested::path::Path;
+/// let mut value: Value = from_str("cluster: {name: es}").unwrap();
+///
+/// let replaced = set_path(
+///     &mut value,
+///     &Path::parse("cluster.routing.0"),
+///     Value::Bool(true),
+/// )
+/// .unwrap();
+/// assert_eq!(replaced, None);
+///
+/// let expected: Value = from_str("cluster: {name: es, routing: [true]}").unwrap();
+/// assert_eq!(value, expected);
+/// ```
+pub fn set_path(value: &mut Value, path: &Path, new_value: Value) -> Result<Option<Value>, Error> {
+    let mut current = value;
+    for (nth, segment) in path.iter().enumerate() {
+        if current.is_null() {
+            *current = nested(path, nth, new_value)?;
+            return Ok(None);
+        }
+
+        let is_last = nth + 1 == path.len();
+        current = match current {
+            Value::Mapping(mapping) => match find_key(mapping, segment) {
+                Some(key) if is_last => return Ok(mapping.insert(key, new_value)),
+                Some(key) => mapping.get_mut(key).expect("the key is just found"),
+                None => {
+                    let key = match segment {
+                        PathSegment::Key(key) => Value::String(key.clone()),
+                        PathSegment::Index(index) => Value::Number((*index).into()),
+                    };
+                    mapping.insert(key, nested(path, nth + 1, new_value)?);
+                    return Ok(None);
+                }
+            },
+            Value::Sequence(sequence) => match segment {
+                PathSegment::Index(index) if *index < sequence.len() && is_last => {
+                    return Ok(Some(std::mem::replace(&mut sequence[*index], new_value)));
+                }
+                PathSegment::Index(index) if *index < sequence.len() => &mut sequence[*index],
+                PathSegment::Index(index) if *index == sequence.len() => {
+                    sequence.push(nested(path, nth + 1, new_value)?);
+                    return Ok(None);
+                }
+                PathSegment::Index(_) => {
+                    return Err(missing_index(path, nth, sequence.len()));
+                }
+                PathSegment::Key(_) => return Err(conflict(path, nth)),
+            },
+            _ => return Err(conflict(path, nth)),
+        };
+    }
+
+    // The path is empty.
+    Ok(Some(std::mem::replace(current, new_value)))
+}
+
+/// Sets the value at the flattened `key` in `value` like [`set_path()`], but
+/// `key` is parsed according to `options`, see [`Path::parse_with()`].
+///
+/// # Errors
+///
+/// Returns the same errors as [`set_path()`].
+pub fn set_path_with(
+    value: &mut Value,
+    key: &str,
+    options: &UnflattenOptions,
+    new_value: Value,
+) -> Result<Option<Value>, Error> {
+    set_path(value, &Path::parse_with(key, options), new_value)
+}
+
+/// Removes the value at `path` in `value` and returns it, or `None` if there
+/// is no such value.
+///
+/// Mappings and sequences on the way that become empty are removed as well,
+/// but `value` itself is always kept. Segments are matched like
+/// [`get_path()`] does, and the elements after a removed sequence element
+/// are shifted. Use [`remove_path_with()`] to remove a flattened key.
+///
+/// # Examples
+///
+/// ```rust
+/// # use serde_yaml_ng::from_str;
+/// # use serde_yaml_ng::Value;
+/// # use serde_yaml_nested::path::remove_path;
+/// # use serde_yaml_nested::path::Path;
+/// let mut value: Value = from_str("{cluster: {routing: {enable: all}}, node: x}").unwrap();
+///
+/// let removed = remove_path(&mut value, &Path::parse("cluster.routing.enable"));
+/// assert_eq!(removed, Some(Value::String("all".into())));
+///
+/// let expected: Value = from_str("node: x").unwrap();
+/// assert_eq!(value, expected);
+/// ```
+pub fn remove_path(value: &mut Value, path: &Path) -> Option<Value> {
+    let segments = path.segments();
+    let (last, parents) = segments.split_last()?;
+    let removed = remove_child(get_segments_mut(value, parents)?, last)?;
+
+    for len in (1..segments.len()).rev() {
+        let parent = get_segments_mut(value, &segments[..len]).expect("the parent exists");
+        let is_empty = match parent {
+            Value::Mapping(mapping) => mapping.is_empty(),
+            Value::Sequence(sequence) => sequence.is_empty(),
+            _ => false,
+        };
+        if !is_empty {
+            break;
+        }
+        let grandparent = get_segments_mut(value, &segments[..len - 1]).expect("it exists");
+        remove_child(grandparent, &segments[len - 1]);
+    }
+
+    Some(removed)
+}
+
+/// Removes the value at the flattened `key` in `value` like
+/// [`remove_path()`], but `key` is parsed according to `options`, see
+/// [`Path::parse_with()`].
+pub fn remove_path_with(value: &mut Value, key: &str, options: &UnflattenOptions) -> Option<Value> {
+    remove_path(value, &Path::parse_with(key, options))
+}
+
+fn get_segments_mut<'a>(value: &'a mut Value, segments: &[PathSegment]) -> Option<&'a mut Value> {
+    segments
+        .iter()
+        .try_fold(value, |value, segment| match value {
+            Value::Mapping(mapping) => {
+                let key = find_key(mapping, segment)?;
+                mapping.get_mut(key)
+            }
+            Value::Sequence(sequence) => sequence.get_mut(as_index(segment)?),
+            _ => None,
+        })
+}
+
+/// Removes the child of `value` at `segment`, `value` is kept even if it
+/// becomes empty.
+pub(crate) fn remove_child(value: &mut Value, segment: &PathSegment) -> Option<Value> {
+    match value {
+        Value::Mapping(mapping) => {
+            let key = find_key(mapping, segment)?;
+            mapping.shift_remove(key)
+        }
+        Value::Sequence(sequence) => {
+            let index = as_index(segment).filter(|index| *index < sequence.len())?;
+            Some(sequence.remove(index))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the key in `mapping` that `segment` matches, see [`get_path()`].
+pub(crate) fn find_key(mapping: &Mapping, segment: &PathSegment) -> Option<Value> {
+    let candidates = match segment {
+        PathSegment::Key(key) => [Some(Value::String(key.clone())), infer_key_type(key)],
+        PathSegment::Index(index) => [
+            Some(Value::Number((*index).into())),
+            Some(Value::String(index.to_string())),
+        ],
+    };
+
+    candidates
+        .into_iter()
+        .flatten()
+        .find(|key| mapping.contains_key(key))
+}
+
+fn as_index(segment: &PathSegment) -> Option<usize> {
+    match segment {
+        PathSegment::Key(_) => None,
+        PathSegment::Index(index) => Some(*index),
+    }
+}
+
+/// Wraps `value` with a mapping or a sequence for each segment of `path`
+/// from the `from`th one.
+fn nested(path: &Path, from: usize, value: Value) -> Result<Value, Error> {
+    path.segments()[from..]
+        .iter()
+        .enumerate()
+        .rev()
+        .try_fold(value, |value, (nth, segment)| match segment {
+            PathSegment::Key(key) => {
+                let mut mapping = Mapping::new();
+                mapping.insert(Value::String(key.clone()), value);
+                Ok(Value::Mapping(mapping))
+            }
+            PathSegment::Index(0) => Ok(Value::Sequence(vec![value])),
+            PathSegment::Index(_) => Err(missing_index(path, from + nth, 0)),
+        })
+}
+
+/// The error for the `nth` segment of `path` that is beyond the end of a
+/// sequence of `len` elements.
+fn missing_index(path: &Path, nth: usize, len: usize) -> Error {
+    Error::InvalidSequence {
+        path: Path::from_iter(path.segments()[..nth].iter().cloned()).to_string(),
+        kind: SequenceErrorKind::MissingIndex { index: len },
+    }
+}
+
+/// The error for the `nth` segment of `path` that cannot be put into the
+/// value at the previous segments.
+fn conflict(path: &Path, nth: usize) -> Error {
+    let token = match nth.checked_sub(1) {
+        Some(previous) => path.segments()[previous].to_string(),
+        None => String::new(),
+    };
+
+    Error::DuplicateValue {
+        key: path.to_string(),
+        token,
+        prior: Path::from_iter(path.segments()[..nth].iter().cloned()).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_yaml_ng::from_str;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Path::parse(""), Path::new());
+        assert_eq!(
+            Path::parse("a.0.01"),
+            Path::from(vec!["a".into(), 0.into(), "01".into()])
+        );
+        assert_eq!(
+            Path::parse(r"a\.b.\0.c\\"),
+            Path::from(vec!["a.b".into(), "0".into(), r"c\".into()])
+        );
+        assert_eq!(Path::parse("a..b").len(), 3);
+        assert_eq!(
+            Path::parse("a.0\\"),
+            Path::from(vec!["a".into(), "0".into()])
+        );
+
+        let escaping = UnflattenOptions::new().escaping(true);
+        for path in ["\\", "a.\\"] {
+            assert_eq!(Path::parse(path), Path::parse_with(path, &escaping));
+            assert_eq!(Path::parse(path).segments().last(), Some(&"".into()));
+        }
+
+        let options = UnflattenOptions::new()
+            .separator("__")
+            .infer_key_types(true);
+        assert_eq!(
+            Path::parse_with("a.b__1__true", &options),
+            Path::from(vec!["a.b".into(), "1".into(), "true".into()])
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let paths = [
+            Path::from(vec!["a".into(), 0.into(), "b".into()]),
+            Path::from(vec!["a.b".into(), r"\".into(), "0".into()]),
+            Path::from(vec!["".into(), "".into()]),
+            Path::from(vec!["".into()]),
+            Path::from(vec![r"\".into()]),
+            Path::new(),
+            Path::from(vec!["10".into(), 10.into(), "true".into()]),
+        ];
+        for path in paths {
+            let displayed = path.to_string();
+            assert_eq!(displayed.parse::<Path>().unwrap(), path, "{}", displayed);
+        }
+
+        assert_eq!(
+            Path::from(vec!["a.b".into(), "0".into(), 0.into()]).to_string(),
+            r"a\.b.\0.0"
+        );
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut paths = vec![
+            Path::parse("a.10"),
+            Path::parse("a.2"),
+            Path::parse("a"),
+            Path::parse("a.2.b"),
+        ];
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                Path::parse("a"),
+                Path::parse("a.2"),
+                Path::parse("a.2.b"),
+                Path::parse("a.10"),
+            ]
+        );
+
+        let path = Path::parse("a.2.b");
+        assert!(path.starts_with(&Path::parse("a.2")));
+        assert!(!path.starts_with(&Path::parse("a.1")));
+        assert_eq!(path.parent(), Some(Path::parse("a.2")));
+        assert_eq!(Path::new().parent(), None);
+    }
+
+    #[test]
+    fn test_get_path() {
+        let value: Value = from_str(
+            r#"
+a:
+  b: [x, {c: y}]
+  1: one
+  true: yes
+  "0": zero
+  d.e: f
+  g: !tag {h: i}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(get_path(&value, &Path::parse("")), Some(&value));
+        assert_eq!(
+            get_path(&value, &Path::parse("a.b.1.c")),
+            Some(&value["a"]["b"][1]["c"])
+        );
+        assert_eq!(
+            get_path(&value, &Path::parse("a.1")),
+            Some(&Value::String("one".into()))
+        );
+        assert_eq!(
+            get_path(&value, &Path::parse("a.true")),
+            Some(&Value::String("yes".into()))
+        );
+        assert_eq!(
+            get_path(&value, &Path::parse("a.0")),
+            Some(&Value::String("zero".into()))
+        );
+        assert_eq!(
+            get_path(&value, &Path::parse(r"a.d\.e")),
+            Some(&Value::String("f".into()))
+        );
+        assert_eq!(get_path(&value, &Path::parse("a.d.e")), None);
+        assert_eq!(get_path(&value, &Path::parse("a.b.2")), None);
+        assert_eq!(get_path(&value, &Path::parse("a.b.c")), None);
+        assert_eq!(get_path(&value, &Path::parse("a.g.h")), None);
+
+        let mut value = value;
+        *get_path_mut(&mut value, &Path::parse("a.b.0")).unwrap() = Value::Null;
+        assert_eq!(value["a"]["b"][0], Value::Null);
+        assert_eq!(get_path_mut(&mut value, &Path::parse("a.x")), None);
+    }
+
+    #[test]
+    fn test_path_with() {
+        let mut value: Value = from_str(r"{'C:\dir': {a.b: x, c: [y]}}").unwrap();
+        let options = UnflattenOptions::new();
+        let escaping = UnflattenOptions::new().escaping(true);
+
+        assert_eq!(get_path(&value, &Path::parse(r"C:\dir")), None);
+        assert_eq!(get_path_with(&value, r"C:\dir", &escaping), None);
+        assert_eq!(
+            get_path_with(&value, r"C:\dir", &options),
+            Some(&value[r"C:\dir"])
+        );
+        assert_eq!(
+            get_path_with(&value, r"C:\\dir/a.b", &escaping.separator("/")),
+            Some(&Value::String("x".into()))
+        );
+
+        *get_path_mut_with(&mut value, r"C:\dir.c", &options).unwrap() = Value::Null;
+        assert_eq!(
+            set_path_with(&mut value, r"C:\dir.d", &options, Value::Bool(true)),
+            Ok(None)
+        );
+        assert_eq!(
+            remove_path_with(&mut value, r"C:\dir.c", &options),
+            Some(Value::Null)
+        );
+        let expected: Value = from_str(r"{'C:\dir': {a.b: x, d: true}}").unwrap();
+        assert_eq!(value, expected);
+    }
+
+    #[test]
+    fn test_set_path() {
+        let mut value = Value::Null;
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.b"), Value::Null),
+            Ok(None)
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.b.c"), Value::Null),
+            Ok(None)
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.d.0.e"), Value::Null),
+            Ok(None)
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.d.1"), Value::Null),
+            Ok(None)
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.d.1"), Value::Bool(true)),
+            Ok(Some(Value::Null))
+        );
+        let expected: Value = from_str("a: {b: {c: null}, d: [{e: null}, true]}").unwrap();
+        assert_eq!(value, expected);
+
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.d.1.f"), Value::Null),
+            Err(Error::DuplicateValue {
+                key: String::from("a.d.1.f"),
+                token: String::from("1"),
+                prior: String::from("a.d.1"),
+            })
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.d.3"), Value::Null),
+            Err(Error::InvalidSequence {
+                path: String::from("a.d"),
+                kind: SequenceErrorKind::MissingIndex { index: 2 },
+            })
+        );
+        assert_eq!(
+            set_path(&mut value, &Path::parse("a.x.1"), Value::Null),
+            Err(Error::InvalidSequence {
+                path: String::from("a.x"),
+                kind: SequenceErrorKind::MissingIndex { index: 0 },
+            })
+        );
+        assert_eq!(value, expected);
+
+        assert_eq!(
+            set_path(&mut value, &Path::parse(""), Value::Null),
+            Ok(Some(expected.clone()))
+        );
+        assert_eq!(value, Value::Null);
+    }
+
+    #[test]
+    fn test_remove_path() {
+        let mut value: Value = from_str("{a: {b: {c: [x]}, d: [y, z]}, e: f}").unwrap();
+
+        assert_eq!(remove_path(&mut value, &Path::parse("a.x")), None);
+        assert_eq!(remove_path(&mut value, &Path::parse("a.d.2")), None);
+        assert_eq!(
+            remove_path(&mut value, &Path::parse("a.d.0")),
+            Some(Value::String("y".into()))
+        );
+        assert_eq!(
+            remove_path(&mut value, &Path::parse("a.b.c.0")),
+            Some(Value::String("x".into()))
+        );
+        let expected: Value = from_str("{a: {d: [z]}, e: f}").unwrap();
+        assert_eq!(value, expected);
+
+        assert_eq!(
+            remove_path(&mut value, &Path::parse("a.d.0")),
+            Some(Value::String("z".into()))
+        );
+        assert_eq!(
+            remove_path(&mut value, &Path::parse("e")),
+            Some(Value::String("f".into()))
+        );
+        assert_eq!(value, Value::Mapping(Mapping::new()));
+        assert_eq!(remove_path(&mut value, &Path::parse("")), None);
+    }
+}